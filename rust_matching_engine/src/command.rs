@@ -0,0 +1,112 @@
+// command.rs
+use crate::matching_engine::{
+    EngineEvent, MarketStatus, MatchingEngine, Order, OrderBookDelta, Trade,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// One inbound command for a market, as it arrives on the wire and is journaled.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "command", content = "payload", rename_all = "PascalCase")]
+pub enum EngineCommand {
+    NewOrder(Order),
+    CancelOrder {
+        order_id: Uuid,
+    },
+    ModifyOrder {
+        order_id: Uuid,
+        #[serde(default, with = "rust_decimal::serde::str_option")]
+        new_price: Option<Decimal>,
+        #[serde(default, with = "rust_decimal::serde::str_option")]
+        new_quantity: Option<Decimal>,
+    },
+    SnapshotRequest {
+        response_channel: String,
+    },
+    /// Admin command switching the market to another `MarketStatus`.
+    SetMarketStatus {
+        status: MarketStatus,
+    },
+}
+
+impl EngineCommand {
+    /// The command's tag, as used in logs and published outputs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EngineCommand::NewOrder(_) => "NewOrder",
+            EngineCommand::CancelOrder { .. } => "CancelOrder",
+            EngineCommand::ModifyOrder { .. } => "ModifyOrder",
+            EngineCommand::SnapshotRequest { .. } => "SnapshotRequest",
+            EngineCommand::SetMarketStatus { .. } => "SetMarketStatus",
+        }
+    }
+
+    /// Applies the command to `engine` and returns everything it produced, including a
+    /// `CommandRejected` event for a cancel or amend the engine refused. Snapshot requests
+    /// change nothing and produce nothing.
+    pub fn apply(
+        &self,
+        engine: &mut MatchingEngine,
+    ) -> (Vec<Trade>, Vec<OrderBookDelta>, Vec<EngineEvent>) {
+        let (trades, deltas, error) = match self {
+            EngineCommand::NewOrder(order) => {
+                let (trades, deltas) = engine.add_order(order.clone());
+                (trades, deltas, None)
+            }
+            EngineCommand::CancelOrder { order_id } => {
+                let (result, deltas) = engine.cancel_order(*order_id);
+                (Vec::new(), deltas, result.err())
+            }
+            EngineCommand::ModifyOrder {
+                order_id,
+                new_price,
+                new_quantity,
+            } => {
+                let (result, trades, deltas) =
+                    engine.amend_order(*order_id, *new_price, *new_quantity);
+                (trades, deltas, result.err())
+            }
+            EngineCommand::SetMarketStatus { status } => {
+                engine.set_status(*status);
+                (Vec::new(), Vec::new(), None)
+            }
+            EngineCommand::SnapshotRequest { .. } => (Vec::new(), Vec::new(), None),
+        };
+        let mut events = engine.take_events();
+        if let Some(error) = error {
+            // Cancels and amends only name the order; an amend refused by the engine left it
+            // in the book, where its client id can be found.
+            let client_order_id = self.client_order_id().map(str::to_string).or_else(|| {
+                self.order_id()
+                    .and_then(|order_id| engine.get_order_by_id(order_id))
+                    .and_then(|order| order.client_order_id)
+            });
+            events.push(EngineEvent::CommandRejected {
+                command: self.name().to_string(),
+                order_id: self.order_id(),
+                client_order_id,
+                error,
+            });
+        }
+        (trades, deltas, events)
+    }
+
+    /// The order the command refers to, if any.
+    pub fn order_id(&self) -> Option<Uuid> {
+        match self {
+            EngineCommand::NewOrder(order) => Some(order.id),
+            EngineCommand::CancelOrder { order_id }
+            | EngineCommand::ModifyOrder { order_id, .. } => Some(*order_id),
+            EngineCommand::SnapshotRequest { .. } | EngineCommand::SetMarketStatus { .. } => None,
+        }
+    }
+
+    /// The client's own id for the order, for commands that carry one.
+    pub fn client_order_id(&self) -> Option<&str> {
+        match self {
+            EngineCommand::NewOrder(order) => order.client_order_id.as_deref(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,318 @@
+// config.rs
+use log::LevelFilter;
+use rust_matching_engine::SelfTradePrevention;
+use rust_matching_engine::journal::FsyncPolicy;
+use rust_matching_engine::redis_transport::{DeltaFormat, Transport};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Environment variables are the setting names upper-cased behind this prefix, e.g.
+/// `MATCHING_ENGINE_REDIS_URL`.
+pub const ENV_PREFIX: &str = "MATCHING_ENGINE_";
+
+/// The settings that can be given as `--<name>` flags (with `-` for `_`) and as
+/// environment variables. Per-market overrides only come from the config file.
+const SETTINGS: [&str; 11] = [
+    "redis_url",
+    "markets_file",
+    "key_prefix",
+    "transport",
+    "delta_format",
+    "journal_dir",
+    "journal_fsync",
+    "snapshot_dir",
+    "snapshot_interval",
+    "log_level",
+    "markets_watch_interval",
+];
+
+pub const USAGE: &str = "\
+Usage: rust_matching_engine [options]
+
+  --config <file>             TOML config file (env MATCHING_ENGINE_CONFIG)
+  --redis-url <url>           Redis to read commands from and publish to
+  --markets-file <file>       JSON list of trading pairs
+  --key-prefix <prefix>       namespace prepended to every Redis key
+  --transport <lists|streams> how commands arrive and processed data leaves
+  --delta-format <format>     batched (one message per command) or per-delta (the old format)
+  --journal-dir <dir>         where command journals are kept
+  --journal-fsync <policy>    always, every:<n> (commands) or never
+  --snapshot-dir <dir>        where book snapshots are kept
+  --snapshot-interval <n>     commands between snapshots
+  --log-level <level>         off, error, warn, info, debug or trace
+  --markets-watch-interval <s> seconds between checks of the markets file (0 to not watch)
+  --print-config              print the resolved config as TOML and exit
+  --input <file|->            run offline, reading commands from a file or stdin
+  --output <file|->           where offline outputs go (stdout by default)
+  --market <symbol>           the market to run offline (the first enabled one by default)
+
+Every setting except --config can also be given as MATCHING_ENGINE_<NAME>, e.g.
+MATCHING_ENGINE_REDIS_URL. Flags override environment variables, which override the
+config file.
+
+Ctrl+C or SIGTERM stops reading commands, publishes everything already read and snapshots
+every book before exiting; a second one exits at once. Exit status: 0 after a clean
+shutdown, 1 if a market failed or could not write its final snapshot, 2 for configuration
+errors, 130 if the shutdown was interrupted.";
+
+/// Engine settings, resolved from the defaults, then the TOML config file, then
+/// environment variables, then command-line flags.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub redis_url: String,
+    /// JSON list of trading pairs.
+    pub markets_file: PathBuf,
+    /// Prepended to every Redis key, separated by `:`. Empty keeps the unprefixed names the
+    /// API layer and websocket server expect by default.
+    pub key_prefix: String,
+    pub transport: Transport,
+    /// What goes out on the deltas channel. `per-delta` keeps the old one-message-per-delta
+    /// format for subscribers that do not understand `BookUpdate` yet.
+    pub delta_format: DeltaFormat,
+    pub journal_dir: PathBuf,
+    /// When journaled commands are forced to disk. Anything but `always` can lose the
+    /// last few commands in a power failure.
+    pub journal_fsync: FsyncPolicy,
+    pub snapshot_dir: PathBuf,
+    /// Take a snapshot once this many commands have been journaled since the last one.
+    pub snapshot_interval: u64,
+    pub log_level: LevelFilter,
+    /// Seconds between checks of the markets file for changes, which start, stop or
+    /// restart the affected markets. 0 only reads it at startup.
+    pub markets_watch_interval: u64,
+    /// Settings that replace the markets file's for one market, keyed by symbol.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub markets: BTreeMap<String, MarketOverrides>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            redis_url: "redis://127.0.0.1:6379".to_string(),
+            markets_file: PathBuf::from("../markets.json"),
+            key_prefix: String::new(),
+            transport: Transport::Lists,
+            delta_format: DeltaFormat::Batched,
+            journal_dir: PathBuf::from("./data/journal"),
+            journal_fsync: FsyncPolicy::Always,
+            snapshot_dir: PathBuf::from("./data/snapshots"),
+            snapshot_interval: 10_000,
+            log_level: LevelFilter::Info,
+            markets_watch_interval: 5,
+            markets: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarketOverrides {
+    pub enabled: Option<bool>,
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    pub dedup_window: Option<usize>,
+    pub snapshot_interval: Option<u64>,
+}
+
+/// Command-line options for an offline run: `--input <file|->` reads commands from a file
+/// (or stdin) instead of Redis, and outputs are written as JSON lines to
+/// `--output <file|->` (stdout by default, where the log lines also go). Only one market
+/// runs offline: `--market <symbol>`, or the first enabled one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineArgs {
+    pub input: String,
+    pub output: String,
+    pub market: Option<String>,
+}
+
+/// What the command line asked for besides settings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunOptions {
+    pub offline: Option<OfflineArgs>,
+    pub print_config: bool,
+    pub help: bool,
+}
+
+impl Config {
+    /// Resolves the config from `args` (without the program name), the environment as seen
+    /// through `env`, and the config file they name, then validates it.
+    pub fn load(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<(Config, RunOptions), String> {
+        let mut options = RunOptions::default();
+        let mut flags = Vec::new();
+        let mut config_file = env(&format!("{}CONFIG", ENV_PREFIX));
+        let mut input = None;
+        let mut output = "-".to_string();
+        let mut market = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--help" | "-h" => options.help = true,
+                "--print-config" => options.print_config = true,
+                "--config" => config_file = Some(value()?),
+                "--input" => input = Some(value()?),
+                "--output" => output = value()?,
+                "--market" => market = Some(value()?),
+                flag => {
+                    let name = flag
+                        .strip_prefix("--")
+                        .map(|name| name.replace('-', "_"))
+                        .filter(|name| SETTINGS.contains(&name.as_str()))
+                        .ok_or(format!("unknown argument '{}'", flag))?;
+                    flags.push((name, value()?));
+                }
+            }
+        }
+        options.offline = input.map(|input| OfflineArgs {
+            input,
+            output,
+            market,
+        });
+        if options.help {
+            return Ok((Config::default(), options));
+        }
+
+        let mut config = match config_file {
+            Some(path) => {
+                let content = fs::read_to_string(&path)
+                    .map_err(|e| format!("failed to read config file '{}': {}", path, e))?;
+                toml::from_str(&content)
+                    .map_err(|e| format!("failed to parse config file '{}': {}", path, e))?
+            }
+            None => Config::default(),
+        };
+        for name in SETTINGS {
+            let var = format!("{}{}", ENV_PREFIX, name.to_uppercase());
+            if let Some(value) = env(&var) {
+                config
+                    .set(name, &value)
+                    .map_err(|e| format!("{}: {}", var, e))?;
+            }
+        }
+        for (name, value) in flags {
+            config
+                .set(&name, &value)
+                .map_err(|e| format!("--{}: {}", name.replace('_', "-"), e))?;
+        }
+
+        config.validate()?;
+        Ok((config, options))
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "redis_url" => self.redis_url = value.to_string(),
+            "markets_file" => self.markets_file = PathBuf::from(value),
+            "key_prefix" => self.key_prefix = value.to_string(),
+            "transport" => {
+                self.transport = match value.to_lowercase().as_str() {
+                    "lists" => Transport::Lists,
+                    "streams" => Transport::Streams,
+                    _ => return Err(format!("unknown transport '{}'", value)),
+                }
+            }
+            "delta_format" => {
+                self.delta_format = match value.to_lowercase().as_str() {
+                    "per-delta" | "perdelta" => DeltaFormat::PerDelta,
+                    "batched" => DeltaFormat::Batched,
+                    _ => return Err(format!("unknown delta format '{}'", value)),
+                }
+            }
+            "journal_dir" => self.journal_dir = PathBuf::from(value),
+            "journal_fsync" => self.journal_fsync = value.parse()?,
+            "snapshot_dir" => self.snapshot_dir = PathBuf::from(value),
+            "snapshot_interval" => {
+                self.snapshot_interval = value
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a command count", value))?
+            }
+            "log_level" => {
+                self.log_level = value
+                    .parse()
+                    .map_err(|_| format!("unknown log level '{}'", value))?
+            }
+            "markets_watch_interval" => {
+                self.markets_watch_interval = value
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a number of seconds", value))?
+            }
+            _ => unreachable!("'{}' is not in SETTINGS", name),
+        }
+        Ok(())
+    }
+
+    /// Checks everything that can be checked without the markets file. Every problem is
+    /// reported, one per line.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        let schemes = ["redis://", "rediss://", "unix://", "redis+unix://"];
+        if !schemes
+            .iter()
+            .any(|scheme| self.redis_url.starts_with(scheme))
+        {
+            problems.push(format!(
+                "redis_url '{}' must start with one of {}",
+                self.redis_url,
+                schemes.join(", ")
+            ));
+        }
+        if self.key_prefix.chars().any(char::is_whitespace) || self.key_prefix.ends_with(':') {
+            problems.push(format!(
+                "key_prefix '{}' must not contain whitespace or end with ':'",
+                self.key_prefix
+            ));
+        }
+        if self.snapshot_interval == 0 {
+            problems.push("snapshot_interval must be at least 1".to_string());
+        }
+        for (symbol, overrides) in &self.markets {
+            if overrides.snapshot_interval == Some(0) {
+                problems.push(format!(
+                    "markets.\"{}\".snapshot_interval must be at least 1",
+                    symbol
+                ));
+            }
+            if overrides.dedup_window == Some(0) {
+                problems.push(format!(
+                    "markets.\"{}\".dedup_window must be at least 1",
+                    symbol
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "invalid configuration:\n  {}",
+                problems.join("\n  ")
+            ))
+        }
+    }
+
+    /// Checks that every per-market override names a market in the markets file.
+    pub fn validate_markets(&self, symbols: &[&str]) -> Result<(), String> {
+        match self
+            .markets
+            .keys()
+            .find(|symbol| !symbols.contains(&symbol.as_str()))
+        {
+            Some(symbol) => Err(format!(
+                "config overrides market '{}', which is not in '{}'",
+                symbol,
+                self.markets_file.display()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The overrides for `symbol`, all unset if it has none.
+    pub fn market(&self, symbol: &str) -> MarketOverrides {
+        self.markets.get(symbol).cloned().unwrap_or_default()
+    }
+}
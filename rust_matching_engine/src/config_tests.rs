@@ -0,0 +1,229 @@
+use crate::config::*;
+use log::LevelFilter;
+use rust_matching_engine::SelfTradePrevention;
+use rust_matching_engine::journal::FsyncPolicy;
+use rust_matching_engine::redis_transport::{DeltaFormat, Transport};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+fn write_config_file(content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("config-test-{}.toml", Uuid::new_v4()));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn test_defaults_without_any_source() {
+    let (config, options) = Config::load(args(&[]), env(&[])).unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(options, RunOptions::default());
+    // One framed update per command unless the old format is asked for.
+    assert_eq!(config.delta_format, DeltaFormat::Batched);
+}
+
+#[test]
+fn test_flags_override_env_which_overrides_file() {
+    let path = write_config_file(
+        r#"
+redis_url = "redis://file:6379"
+key_prefix = "file"
+journal_dir = "/file/journal"
+log_level = "debug"
+
+[markets."BTC/INR"]
+enabled = false
+self_trade_prevention = "CancelBoth"
+dedup_window = 50
+"#,
+    );
+    let (config, _) = Config::load(
+        args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--key-prefix",
+            "flag",
+            "--transport",
+            "streams",
+            "--journal-fsync",
+            "every:50",
+        ]),
+        env(&[
+            ("MATCHING_ENGINE_KEY_PREFIX", "env"),
+            ("MATCHING_ENGINE_JOURNAL_DIR", "/env/journal"),
+            ("MATCHING_ENGINE_DELTA_FORMAT", "per-delta"),
+        ]),
+    )
+    .unwrap();
+
+    assert_eq!(config.redis_url, "redis://file:6379");
+    assert_eq!(config.journal_dir, PathBuf::from("/env/journal"));
+    assert_eq!(config.key_prefix, "flag");
+    assert_eq!(config.transport, Transport::Streams);
+    assert_eq!(config.delta_format, DeltaFormat::PerDelta);
+    assert_eq!(config.journal_fsync, FsyncPolicy::EveryN(50));
+    assert_eq!(config.log_level, LevelFilter::Debug);
+    let btc = config.market("BTC/INR");
+    assert_eq!(btc.enabled, Some(false));
+    assert_eq!(
+        btc.self_trade_prevention,
+        Some(SelfTradePrevention::CancelBoth)
+    );
+    assert_eq!(btc.dedup_window, Some(50));
+    assert_eq!(config.market("ETH/USD"), MarketOverrides::default());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_file_from_env_and_unknown_keys_rejected() {
+    let path = write_config_file("redis_uri = \"redis://typo:6379\"\n");
+    let error = Config::load(
+        args(&[]),
+        env(&[("MATCHING_ENGINE_CONFIG", path.to_str().unwrap())]),
+    )
+    .unwrap_err();
+    assert!(error.contains("redis_uri"), "{}", error);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_validation_reports_every_problem() {
+    let path = write_config_file(
+        r#"
+[markets."BTC/INR"]
+snapshot_interval = 0
+"#,
+    );
+    let error = Config::load(
+        args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--redis-url",
+            "localhost:6379",
+            "--key-prefix",
+            "staging:",
+        ]),
+        env(&[]),
+    )
+    .unwrap_err();
+    assert!(error.contains("redis_url 'localhost:6379'"), "{}", error);
+    assert!(error.contains("key_prefix 'staging:'"), "{}", error);
+    assert!(
+        error.contains("markets.\"BTC/INR\".snapshot_interval"),
+        "{}",
+        error
+    );
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_bad_values_name_their_source() {
+    let error = Config::load(
+        args(&[]),
+        env(&[("MATCHING_ENGINE_SNAPSHOT_INTERVAL", "often")]),
+    )
+    .unwrap_err();
+    assert!(
+        error.starts_with("MATCHING_ENGINE_SNAPSHOT_INTERVAL:"),
+        "{}",
+        error
+    );
+
+    let error = Config::load(args(&["--log-level", "loud"]), env(&[])).unwrap_err();
+    assert!(error.starts_with("--log-level:"), "{}", error);
+
+    let error = Config::load(args(&["--journal-fsync", "every:0"]), env(&[])).unwrap_err();
+    assert!(error.starts_with("--journal-fsync:"), "{}", error);
+
+    let error = Config::load(args(&["--delta-format", "json"]), env(&[])).unwrap_err();
+    assert_eq!(error, "--delta-format: unknown delta format 'json'");
+
+    let error = Config::load(args(&["--verbose"]), env(&[])).unwrap_err();
+    assert_eq!(error, "unknown argument '--verbose'");
+}
+
+#[test]
+fn test_offline_args_and_print_config() {
+    let (_, options) = Config::load(
+        args(&["--input", "-", "--market", "ETH/USD", "--print-config"]),
+        env(&[]),
+    )
+    .unwrap();
+    assert!(options.print_config);
+    assert_eq!(
+        options.offline,
+        Some(OfflineArgs {
+            input: "-".to_string(),
+            output: "-".to_string(),
+            market: Some("ETH/USD".to_string()),
+        })
+    );
+}
+
+#[test]
+fn test_printed_config_loads_back_unchanged() {
+    let path = write_config_file(
+        r#"
+key_prefix = "staging"
+transport = "streams"
+delta_format = "per-delta"
+journal_fsync = "never"
+
+[markets."ETH/USD"]
+snapshot_interval = 500
+"#,
+    );
+    let (config, _) = Config::load(args(&["--config", path.to_str().unwrap()]), env(&[])).unwrap();
+    assert_eq!(config.transport, Transport::Streams);
+    assert_eq!(config.delta_format, DeltaFormat::PerDelta);
+    let printed = toml::to_string_pretty(&config).unwrap();
+    assert!(printed.contains("transport = \"streams\""));
+    assert!(printed.contains("delta_format = \"per-delta\""));
+    fs::write(&path, printed).unwrap();
+    let (reloaded, _) =
+        Config::load(args(&["--config", path.to_str().unwrap()]), env(&[])).unwrap();
+    assert_eq!(reloaded, config);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_file_accepts_the_old_capitalized_spellings() {
+    let path = write_config_file(
+        r#"
+transport = "Streams"
+delta_format = "PerDelta"
+"#,
+    );
+    let (config, _) = Config::load(args(&["--config", path.to_str().unwrap()]), env(&[])).unwrap();
+    assert_eq!(config.transport, Transport::Streams);
+    assert_eq!(config.delta_format, DeltaFormat::PerDelta);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_overrides_must_name_known_markets() {
+    let mut config = Config::default();
+    config
+        .markets
+        .insert("DOGE/INR".to_string(), MarketOverrides::default());
+    assert!(config.validate_markets(&["BTC/INR", "ETH/USD"]).is_err());
+    assert!(config.validate_markets(&["BTC/INR", "DOGE/INR"]).is_ok());
+}
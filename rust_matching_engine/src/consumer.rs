@@ -1,357 +1,575 @@
 // consumer.rs
-use crate::matching_engine::{
-    DeltaAction, MatchingEngine, Order, OrderBookDelta, OrderSide, Trade,
+use crate::command::EngineCommand;
+use crate::journal::{CommandSource, Journal, RecordedOutput};
+use crate::matching_engine::{EngineError, EngineEvent, MatchingEngine};
+use crate::snapshot::SnapshotStore;
+use crate::transport::{
+    CommandOutput, InboundCommand, InputSource, Output, OutputSink, StopSignal,
 };
-use redis::{AsyncCommands, pipe};
-use rust_decimal::prelude::ToPrimitive;
-use serde::Deserialize;
-use serde_json;
+use log::{debug, error, info, warn};
+use std::fmt;
+use std::io;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, mpsc, watch};
 use uuid::Uuid;
 
-#[derive(Deserialize, Debug)]
-#[serde(tag = "command", content = "payload", rename_all = "PascalCase")]
-enum EngineCommand {
-    NewOrder(Order),
-    CancelOrder { order_id: Uuid },
-    SnapshotRequest { response_channel: String },
+// Bounds of the channels between the input, matching and publisher stages.
+const INBOUND_CHANNEL_CAPACITY: usize = 1024;
+const OUTBOUND_CHANNEL_CAPACITY: usize = 1024;
+
+/// Times the publisher tries to deliver an output, and to acknowledge its stream entry,
+/// before moving on. The wait between tries starts at `PUBLISH_RETRY_BACKOFF` and doubles,
+/// which gives a Redis connection time to come back.
+const PUBLISH_ATTEMPTS: u32 = 5;
+const PUBLISH_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// What the matching stage hands the publisher stage for one inbound command.
+struct Outbound {
+    /// `None` when there is nothing to publish, e.g. a redelivered entry that was already
+    /// applied.
+    output: Option<Output>,
+    /// Set for commands that were journaled and reached the engine.
+    applied: Option<Applied>,
+    /// The stream entry to acknowledge once `output` has been published.
+    ack: Option<CommandSource>,
 }
 
+/// What the publisher stage hands the output sink, retried as a whole.
+#[derive(Clone, Copy)]
+enum Delivery<'a> {
+    Publish(&'a Output),
+    Acknowledge(&'a CommandSource),
+}
+
+impl fmt::Display for Delivery<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delivery::Publish(_) => write!(f, "publish outputs"),
+            Delivery::Acknowledge(source) => {
+                write!(f, "acknowledge entry {} on '{}'", source.id, source.stream)
+            }
+        }
+    }
+}
+
+/// A command the matching stage journaled and applied.
+struct Applied {
+    /// Its journal sequence number.
+    sequence: u64,
+    /// Time spent in the engine.
+    match_time: Duration,
+}
+
+/// How far the publisher stage has got, in journal sequence numbers.
+#[derive(Debug, Clone, Copy)]
+struct PublishProgress {
+    /// Last command the publisher has dealt with, whether its output was delivered or not.
+    handled: u64,
+    /// Last command through which every output was delivered and every stream entry
+    /// acknowledged. Snapshots never go past it, so the journal keeps the recorded output
+    /// of anything a stream may still redeliver.
+    published: u64,
+    /// Set once the output of a stream entry could not be delivered or the entry
+    /// acknowledged. `published` stops there for the rest of the run, so that the journal
+    /// keeps the output for the redelivery to publish.
+    failed: bool,
+}
+
+/// Runs one market: reads commands from an `InputSource`, journals and applies them to
+/// the engine, and hands the results to an `OutputSink`.
 pub struct OrderConsumer {
-    redis_client: redis::aio::ConnectionManager,
     engine: Arc<Mutex<MatchingEngine>>,
+    input: Mutex<Box<dyn InputSource>>,
+    output: Mutex<Box<dyn OutputSink>>,
+    journal: Mutex<Journal>,
+    snapshots: SnapshotStore,
+    // Take a snapshot once this many commands have been journaled since the last one.
+    snapshot_interval: u64,
     symbol: String,
-    order_queue_key: String,
-    cancel_queue_key: String,
-    snapshot_request_queue_key: String,
-    trade_channel_key: String,
-    processed_trades_queue_key: String,
-    processed_orders_queue_key: String,
-    ltp_key: String,
-    delta_channel_key: String,
-    bids_orderbook_key: String,
-    asks_orderbook_key: String,
+    latency: Mutex<LatencyStats>,
+    // Set by `stop`; the input stage watches it.
+    stop: watch::Sender<bool>,
+    // Reported by the publisher stage; checkpoints wait on it.
+    progress: watch::Sender<PublishProgress>,
+}
+
+/// Number of commands summarised in each latency report.
+const LATENCY_REPORT_INTERVAL: u32 = 1000;
+
+/// Time spent per command in the engine and in publishing its outputs, printed and reset
+/// every `LATENCY_REPORT_INTERVAL` commands.
+#[derive(Default)]
+struct LatencyStats {
+    commands: u32,
+    match_total: Duration,
+    match_max: Duration,
+    publish_total: Duration,
+    publish_max: Duration,
+}
+
+impl LatencyStats {
+    fn record(&mut self, symbol: &str, match_time: Duration, publish_time: Duration) {
+        self.commands += 1;
+        self.match_total += match_time;
+        self.match_max = self.match_max.max(match_time);
+        self.publish_total += publish_time;
+        self.publish_max = self.publish_max.max(publish_time);
+        if self.commands >= LATENCY_REPORT_INTERVAL {
+            info!(
+                "[{}] Latency over {} commands: matching avg {:?} max {:?}, publishing avg {:?} max {:?}",
+                symbol,
+                self.commands,
+                self.match_total / self.commands,
+                self.match_max,
+                self.publish_total / self.commands,
+                self.publish_max
+            );
+            *self = LatencyStats::default();
+        }
+    }
+}
+
+/// Builds the rejection for a payload that did not parse as an `EngineCommand`,
+/// salvaging whatever identifies the order so the client can be told.
+fn reject_malformed_command(data_json: &str, error: &serde_json::Error) -> EngineEvent {
+    let value: serde_json::Value = serde_json::from_str(data_json).unwrap_or_default();
+    let payload = &value["payload"];
+    let order_id = payload["order_id"]
+        .as_str()
+        .or_else(|| payload["id"].as_str())
+        .and_then(|id| Uuid::parse_str(id).ok());
+    EngineEvent::CommandRejected {
+        command: value["command"].as_str().unwrap_or("Unknown").to_string(),
+        order_id,
+        client_order_id: payload["client_order_id"].as_str().map(str::to_string),
+        error: EngineError::MalformedCommand {
+            message: error.to_string(),
+        },
+    }
 }
 
 impl OrderConsumer {
-    #[allow(clippy::too_many_arguments)]
-    pub async fn new(
-        redis_url: &str,
+    pub fn new(
         engine: Arc<Mutex<MatchingEngine>>,
+        input: Box<dyn InputSource>,
+        output: Box<dyn OutputSink>,
+        journal: Journal,
+        snapshots: SnapshotStore,
+        snapshot_interval: u64,
         symbol: String,
-        order_queue_key: String,
-        cancel_queue_key: String,
-        trade_channel_key: String,
-        processed_trades_queue_key: String,
-        processed_orders_queue_key: String,
-        ltp_key: String,
-        snapshot_key: String, // We'll use this for snapshot requests
-        delta_channel_key: String,
-        bids_orderbook_key: String,
-        asks_orderbook_key: String,
-    ) -> Result<Self, redis::RedisError> {
-        let redis_client = redis::Client::open(redis_url)?;
-        let connection_manager = redis::aio::ConnectionManager::new(redis_client).await?;
-
-        // Extract symbol from keys for Redis native storage
-        let symbol_part = symbol.clone();
-        let snapshot_request_queue_key = format!("{}:requests", snapshot_key);
-
-        Ok(OrderConsumer {
-            redis_client: connection_manager,
+    ) -> Self {
+        // Whatever the journal held at startup was published before, or is republished
+        // from its recorded output when the stream redelivers it ahead of new entries.
+        let progress = PublishProgress {
+            handled: journal.last_sequence(),
+            published: journal.last_sequence(),
+            failed: false,
+        };
+        OrderConsumer {
             engine,
-            symbol: symbol_part,
-            order_queue_key,
-            cancel_queue_key,
-            snapshot_request_queue_key,
-            trade_channel_key,
-            processed_trades_queue_key,
-            processed_orders_queue_key,
-            ltp_key,
-            delta_channel_key,
-            bids_orderbook_key,
-            asks_orderbook_key,
-        })
+            input: Mutex::new(input),
+            output: Mutex::new(output),
+            journal: Mutex::new(journal),
+            snapshots,
+            snapshot_interval,
+            symbol,
+            latency: Mutex::new(LatencyStats::default()),
+            stop: watch::channel(false).0,
+            progress: watch::channel(progress).0,
+        }
     }
 
-    async fn publish_deltas(&self, deltas: Vec<OrderBookDelta>) {
-        if deltas.is_empty() {
-            return;
-        }
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
 
-        let mut con = self.redis_client.clone();
-        for delta in deltas {
-            if let Ok(delta_json) = serde_json::to_string(&delta) {
-                // PUBLISH is a fire-and-forget broadcast
-                let _: Result<(), _> = con.publish(&self.delta_channel_key, delta_json).await;
+    /// Asks a running consumer to stop reading input. `run_consumer` then returns once
+    /// every command already read has been applied and published and the book has been
+    /// snapshotted.
+    pub fn stop(&self) {
+        self.stop.send_replace(true);
+    }
+
+    /// Snapshots the engine once enough commands have been journaled, then drops the
+    /// journal entries the snapshot covers. Called by the matching stage between commands,
+    /// so it first waits for the publisher to catch up with everything applied so far.
+    async fn checkpoint_if_due(&self) {
+        let through = {
+            let journal = self.journal.lock().await;
+            if journal.entry_count() < self.snapshot_interval {
+                return;
             }
+            journal.last_sequence()
+        };
+        // Nothing is snapshotted after a failed publish; the failure was logged.
+        if self.progress.borrow().failed {
+            return;
         }
+        // The sender lives as long as `self`, so this returns once the publisher is done.
+        let _ = self
+            .progress
+            .subscribe()
+            .wait_for(|progress| progress.handled >= through)
+            .await;
+        // A failure is logged, and the next due checkpoint tries again.
+        let _ = self.checkpoint(&mut *self.journal.lock().await).await;
     }
 
-    async fn publish_processed_order(&self, order: &Order) {
-        if let Ok(order_json) = serde_json::to_string(order) {
-            let mut con = self.redis_client.clone();
-            let _: Result<(), _> = con
-                .lpush(&self.processed_orders_queue_key, order_json)
-                .await;
-        } else {
-            eprintln!(
-                "[{}] Failed to serialize processed order for DB queue",
-                self.symbol
+    /// Snapshots the engine if anything has been journaled since the last snapshot, then
+    /// drops the journal entries the snapshot covers. Fails without snapshotting while the
+    /// outputs of some of those entries have not been published and acknowledged: the
+    /// journal has to keep them for a redelivery to publish again.
+    async fn checkpoint(&self, journal: &mut Journal) -> io::Result<()> {
+        if journal.entry_count() == 0 {
+            return Ok(());
+        }
+        let sequence = journal.last_sequence();
+        let published = self.progress.borrow().published;
+        if published < sequence {
+            error!(
+                "[{}] Not snapshotting: outputs after journal sequence {} were not published, \
+                 so the journal keeps them for a restart",
+                self.symbol, published
             );
+            return Err(io::Error::other(format!(
+                "outputs after journal sequence {} were not published",
+                published
+            )));
         }
+        let state = self.engine.lock().await.export_state();
+        let path = self
+            .snapshots
+            .write(sequence, journal.stream_positions(), &state)
+            .inspect_err(|e| error!("[{}] Failed to write snapshot: {}", self.symbol, e))?;
+        info!(
+            "[{}] Snapshot written to '{}' at journal sequence {}",
+            self.symbol,
+            path.display(),
+            sequence
+        );
+        journal
+            .truncate_through(sequence)
+            .inspect_err(|e| error!("[{}] Failed to truncate journal: {}", self.symbol, e))
     }
 
-    async fn update_redis_orderbook(&self, deltas: &[OrderBookDelta]) {
-        if deltas.is_empty() {
-            return;
-        }
-
-        let mut con = self.redis_client.clone();
-
-        for delta in deltas {
-            let key = match delta.side {
-                OrderSide::Buy => &self.bids_orderbook_key,
-                OrderSide::Sell => &self.asks_orderbook_key,
-            };
-            let score = if delta.side == OrderSide::Buy {
-                -delta.price.to_f64().unwrap_or(0.0)
-            } else {
-                delta.price.to_f64().unwrap_or(0.0)
-            };
-            let _: Result<i32, _> = con.zrembyscore(key, score, score).await;
-            match delta.action {
-                DeltaAction::Delete => {
-                    // Nothing more to do - entry already removed
-                }
-                DeltaAction::New | DeltaAction::Update => {
-                    // Score is the price (for sorting), member is price:quantity
-                    let member = format!("{}:{}", delta.price, delta.new_quantity);
-                    // Add new entry
-                    let _: Result<i32, _> = con.zadd(key, member, score).await;
-                }
+    /// Runs the input, matching and publishing stages until the input is exhausted or
+    /// `stop` is called and everything read from it has been published, then syncs the
+    /// journal and snapshots the book so a restart does not have to replay it. Fails if the
+    /// journal could not be synced or the final snapshot written, including when an output
+    /// could not be published and the book was left unsnapshotted for its redelivery.
+    ///
+    /// The stages run concurrently and hand work on through bounded FIFO channels, so the
+    /// engine keeps matching while earlier outputs are still being published, outputs
+    /// leave in command order, and a full channel slows down the stage feeding it.
+    pub async fn run_consumer(&self) -> io::Result<()> {
+        info!(
+            "[{}] Consumer started. Listening for orders, cancellations, and snapshot requests...",
+            self.symbol
+        );
+        let snapshot = self.engine.lock().await.get_order_book_snapshot();
+        self.output.lock().await.initialize(&snapshot).await;
+
+        let (inbound_tx, inbound_rx) = mpsc::channel(INBOUND_CHANNEL_CAPACITY);
+        let (outbound_tx, outbound_rx) = mpsc::channel(OUTBOUND_CHANNEL_CAPACITY);
+        tokio::join!(
+            self.run_input(inbound_tx),
+            self.run_matching(inbound_rx, outbound_tx),
+            self.run_publisher(outbound_rx)
+        );
+        let mut journal = self.journal.lock().await;
+        let checkpointed = match journal.sync() {
+            Ok(()) => self.checkpoint(&mut journal).await,
+            Err(e) => {
+                error!("[{}] Failed to sync journal: {}", self.symbol, e);
+                Err(e)
             }
-        }
+        };
+        info!("[{}] Consumer stopped.", self.symbol);
+        checkpointed
     }
 
-    async fn publish_trades_and_ltp(&self, trades: Vec<Trade>) {
-        if trades.is_empty() {
-            return;
-        }
-
-        let mut con = self.redis_client.clone();
-
-        // This pipeline is for the database queue, ensuring trades are saved.
-        let mut db_queue_pipeline = pipe();
-        for trade in &trades {
-            if let Ok(trade_json) = serde_json::to_string(trade) {
-                // Action 1: Publish to the real-time Pub/Sub channel.
-                // This is "fire and forget" for live listeners.
-                let _: Result<(), _> = con.publish(&self.trade_channel_key, &trade_json).await;
-
-                // Action 2: Add the trade to the persistent queue for the DB worker.
-                db_queue_pipeline
-                    .lpush(&self.processed_trades_queue_key, trade_json)
-                    .ignore();
+    async fn run_input(&self, inbound: mpsc::Sender<InboundCommand>) {
+        let mut input = self.input.lock().await;
+        let mut stop = StopSignal::new(self.stop.subscribe());
+        while let Some(command) = input.next_command(&mut stop).await {
+            if inbound.send(command).await.is_err() {
+                return;
             }
         }
-        // Execute the pipeline to push all trades to the DB queue.
-        let _: Result<(), _> = db_queue_pipeline.query_async(&mut con).await;
+    }
 
-        // Update LTP
-        if let Some(last_trade) = trades.last() {
-            let _: Result<(), _> = con.set(&self.ltp_key, last_trade.price.to_string()).await;
+    /// Applies inbound commands to the engine one at a time and passes their outputs on.
+    async fn run_matching(
+        &self,
+        mut inbound: mpsc::Receiver<InboundCommand>,
+        outbound: mpsc::Sender<Outbound>,
+    ) {
+        while let Some(command) = inbound.recv().await {
+            let (output, applied) = self.handle_message(&command).await;
+            let message = Outbound {
+                output,
+                applied,
+                ack: command.source,
+            };
+            if outbound.send(message).await.is_err() {
+                break;
+            }
+            self.checkpoint_if_due().await;
         }
     }
 
-    async fn handle_snapshot_request(&self, response_channel: String) {
-        println!(
-            "[{}] Generating snapshot for channel: {}",
-            self.symbol, response_channel
-        );
-
-        let engine_guard = self.engine.lock().await;
-        let snapshot = engine_guard.get_order_book_snapshot();
-        drop(engine_guard);
-
-        if let Ok(snapshot_json) = serde_json::to_string(&snapshot) {
-            let mut con = self.redis_client.clone();
-            let _: Result<(), _> = con.publish(&response_channel, snapshot_json).await;
-            println!(
-                "[{}] Snapshot sent to channel: {}",
-                self.symbol, response_channel
-            );
-        } else {
-            eprintln!(
-                "[{}] Failed to serialize snapshot for channel: {}",
-                self.symbol, response_channel
-            );
+    /// Publishes outputs in the order the matching stage produced them, acknowledges the
+    /// stream entry each one came from once its output has been delivered, and reports
+    /// how far it got in `progress`. Both are retried with backoff before moving on.
+    async fn run_publisher(&self, mut outbound: mpsc::Receiver<Outbound>) {
+        let mut sink = self.output.lock().await;
+        while let Some(message) = outbound.recv().await {
+            let started = Instant::now();
+            let mut delivered = match &message.output {
+                Some(output) => self.deliver(&mut **sink, Delivery::Publish(output)).await,
+                None => Ok(()),
+            };
+            let publish_time = started.elapsed();
+            if let (Ok(()), Some(source)) = (&delivered, &message.ack) {
+                delivered = self
+                    .deliver(&mut **sink, Delivery::Acknowledge(source))
+                    .await;
+            }
+            // A stream entry left unacknowledged is redelivered on the next start and its
+            // recorded output published again, so the journal has to keep it. Nothing
+            // redelivers a list item: its output is lost.
+            let redeliverable = message.ack.is_some();
+            match &delivered {
+                Ok(()) => {
+                    if let Some(applied) = &message.applied {
+                        self.latency.lock().await.record(
+                            &self.symbol,
+                            applied.match_time,
+                            publish_time,
+                        );
+                    }
+                }
+                Err(e) if redeliverable => error!(
+                    "[{}] Failed to publish outputs, no snapshots until a restart: {}",
+                    self.symbol, e
+                ),
+                Err(e) => error!(
+                    "[{}] Failed to publish outputs, dropping them: {}",
+                    self.symbol, e
+                ),
+            }
+            self.progress.send_modify(|progress| {
+                progress.failed |= delivered.is_err() && redeliverable;
+                if let Some(applied) = &message.applied {
+                    progress.handled = applied.sequence;
+                    if !progress.failed {
+                        progress.published = applied.sequence;
+                    }
+                }
+            });
         }
     }
 
-    pub async fn run_consumer(&self) {
-        println!(
-            "[{}] Consumer started. Listening for orders, cancellations, and snapshot requests...",
-            self.symbol
-        );
-        let queues = vec![
-            self.order_queue_key.as_str(),
-            self.cancel_queue_key.as_str(),
-            self.snapshot_request_queue_key.as_str(),
-        ];
-
+    /// Tries `delivery` until it succeeds or has failed `PUBLISH_ATTEMPTS` times, and
+    /// returns the last result.
+    async fn deliver(&self, sink: &mut dyn OutputSink, delivery: Delivery<'_>) -> io::Result<()> {
+        let mut backoff = PUBLISH_RETRY_BACKOFF;
+        let mut attempt = 1;
         loop {
-            let mut con = self.redis_client.clone();
-            let result: Result<Option<(String, String)>, redis::RedisError> =
-                con.brpop(&queues, 0.0).await;
-
+            let result = match delivery {
+                Delivery::Publish(output) => sink.publish(output).await,
+                Delivery::Acknowledge(source) => sink.acknowledge(source).await,
+            };
             match result {
-                Ok(Some((queue, data_json))) => {
-                    println!("[{}] Received command from queue '{}'", self.symbol, queue);
-
-                    // Handle snapshot requests separately as they don't lock the main engine
-                    // or produce trades/deltas in the same way.
-                    if queue == self.snapshot_request_queue_key {
-                        if let Ok(snapshot_request) =
-                            serde_json::from_str::<EngineCommand>(&data_json)
-                        {
-                            if let EngineCommand::SnapshotRequest { response_channel } =
-                                snapshot_request
-                            {
-                                self.handle_snapshot_request(response_channel).await;
-                            }
-                        }
-                        continue; // Go to the next loop iteration
-                    }
-
-                    // Process NewOrder and CancelOrder commands
-                    match serde_json::from_str::<EngineCommand>(&data_json) {
-                        Ok(command) => {
-                            // These variables will hold the results from the engine
-                            let (trades, deltas);
-                            // This will hold the original order for logging, if applicable
-                            let mut original_order: Option<Order> = None;
-
-                            // Lock the engine for the shortest time possible
-                            {
-                                let mut engine_guard = self.engine.lock().await;
-
-                                match command {
-                                    EngineCommand::NewOrder(order) => {
-                                        println!(
-                                            "[{}] Processing NewOrder {}",
-                                            self.symbol, order.id
-                                        );
-
-                                        // Clone the original order so we can log it after processing
-                                        original_order = Some(order.clone());
-
-                                        // Pass ownership of the order to the engine
-                                        (trades, deltas) = engine_guard.add_order(order);
-                                    }
-                                    EngineCommand::CancelOrder { order_id } => {
-                                        println!(
-                                            "[{}] Processing CancelOrder {}",
-                                            self.symbol, order_id
-                                        );
-                                        let (result, cancel_deltas) =
-                                            engine_guard.cancel_order(order_id);
-
-                                        // Check if the cancellation was successful
-                                        if let Err(e) = result {
-                                            eprintln!("[{}] {}", self.symbol, e);
-                                        }
-
-                                        trades = Vec::new(); // No trades on a cancellation
-                                        deltas = cancel_deltas;
-                                    }
-                                    // This case is handled above, but included for completeness
-                                    EngineCommand::SnapshotRequest { .. } => {
-                                        trades = Vec::new();
-                                        deltas = Vec::new();
-                                    }
-                                }
-                            } // The engine lock is automatically released here by `drop(engine_guard)`
-
-                            // --- Perform all network-based publishing concurrently ---
-                            if let Some(order_to_log) = original_order {
-                                // This branch is for `NewOrder` commands
-                                tokio::join!(
-                                    self.publish_processed_order(&order_to_log),
-                                    self.publish_deltas(deltas.clone()),
-                                    self.update_redis_orderbook(&deltas),
-                                    self.publish_trades_and_ltp(trades)
-                                );
-                            } else {
-                                // This branch is for `CancelOrder` and other commands
-                                tokio::join!(
-                                    self.publish_deltas(deltas.clone()),
-                                    self.update_redis_orderbook(&deltas),
-                                    self.publish_trades_and_ltp(trades)
-                                );
-                            }
-
-                            println!("[{}] Command processed.", self.symbol);
-                        }
-                        Err(e) => {
-                            eprintln!(
-                                "[{}] Failed to deserialize command from JSON '{}': {}",
-                                self.symbol, data_json, e
-                            );
-                        }
-                    }
-                }
-                Ok(None) => continue, // Should not happen with a 0.0 timeout, but safe to have
-                Err(e) => {
-                    eprintln!(
-                        "[{}] Error from Redis: {}. Retrying in 2s...",
-                        self.symbol, e
+                Err(e) if attempt < PUBLISH_ATTEMPTS => {
+                    warn!(
+                        "[{}] Failed to {}, retrying in {:?}: {}",
+                        self.symbol, delivery, backoff, e
                     );
-                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
                 }
+                result => return result,
             }
         }
     }
 
-    // Helper method to initialize the Redis orderbook on startup
-    pub async fn initialize_redis_orderbook(&self) {
-        let mut con = self.redis_client.clone();
+    /// The output to publish again for a redelivered stream entry that was applied before a
+    /// crash, or `None` if there is nothing to publish: the entry's output was never
+    /// recorded, or a snapshot has covered it, which only happens once it was acknowledged.
+    fn recorded_output(&self, journal: &Journal, source: &CommandSource) -> Option<Output> {
+        let entry = match journal.find(source) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                debug!(
+                    "[{}] Skipping already applied entry {} from '{}'",
+                    self.symbol, source.id, source.stream
+                );
+                return None;
+            }
+            Err(e) => {
+                error!(
+                    "[{}] Failed to look up entry {} from '{}' in the journal: {}",
+                    self.symbol, source.id, source.stream, e
+                );
+                return None;
+            }
+        };
+        let Some(recorded) = entry.output else {
+            warn!(
+                "[{}] Entry {} from '{}' was applied but its output was not recorded",
+                self.symbol, source.id, source.stream
+            );
+            return None;
+        };
+        info!(
+            "[{}] Publishing the recorded output of redelivered entry {} from '{}' again",
+            self.symbol, source.id, source.stream
+        );
+        Some(self.command_output(entry.command.name(), entry.sequence, recorded))
+    }
 
-        // Clear existing orderbook data
-        let _: Result<(), _> = con
-            .del(&[&self.bids_orderbook_key, &self.asks_orderbook_key])
-            .await;
+    fn command_output(
+        &self,
+        command: &'static str,
+        command_id: u64,
+        recorded: RecordedOutput,
+    ) -> Output {
+        Output::Command(CommandOutput {
+            symbol: self.symbol.clone(),
+            command,
+            command_id,
+            trades: recorded.trades,
+            deltas: recorded.deltas,
+            events: recorded.events,
+        })
+    }
 
-        // Get initial snapshot and populate Redis
-        let engine_guard = self.engine.lock().await;
-        let snapshot = engine_guard.get_order_book_snapshot();
-        drop(engine_guard);
-
-        // Populate bids
-        if !snapshot.bids.is_empty() {
-            for bid in &snapshot.bids {
-                let member = format!("{}:{}", bid.price, bid.quantity);
-                let score = -bid.price.to_f64().unwrap_or(0.0); // Negative for descending order
-                let _: Result<i32, _> = con.zadd(&self.bids_orderbook_key, member, score).await;
+    /// Handles one inbound command and returns what has to be published for it, with its
+    /// journal sequence and the time the engine took when the command reached it.
+    async fn handle_message(&self, inbound: &InboundCommand) -> (Option<Output>, Option<Applied>) {
+        let InboundCommand {
+            origin,
+            data_json,
+            source,
+            admin,
+        } = inbound;
+        let source = source.as_ref();
+        debug!("[{}] Received command from '{}'", self.symbol, origin);
+
+        match serde_json::from_str::<EngineCommand>(data_json) {
+            // Snapshot requests are answered from the book as it stands between commands,
+            // so the snapshot lines up with the sequence numbers published before it.
+            Ok(EngineCommand::SnapshotRequest { response_channel }) => {
+                debug!(
+                    "[{}] Generating snapshot for channel: {}",
+                    self.symbol, response_channel
+                );
+                let snapshot = self.engine.lock().await.get_order_book_snapshot();
+                let output = Output::Snapshot {
+                    response_channel,
+                    snapshot,
+                };
+                (Some(output), None)
             }
-        }
-
-        // Populate asks
-        if !snapshot.asks.is_empty() {
-            for ask in &snapshot.asks {
-                let member = format!("{}:{}", ask.price, ask.quantity);
-                let score = ask.price.to_f64().unwrap_or(0.0); // Positive for ascending order
-                let _: Result<i32, _> = con.zadd(&self.asks_orderbook_key, member, score).await;
+            // Whoever can place orders must not be able to halt the market.
+            Ok(command @ EngineCommand::SetMarketStatus { .. }) if !admin => {
+                let error = EngineError::AdminOnly {
+                    origin: origin.clone(),
+                };
+                warn!("[{}] Refusing {}: {}", self.symbol, command.name(), error);
+                let rejection = EngineEvent::CommandRejected {
+                    command: command.name().to_string(),
+                    order_id: None,
+                    client_order_id: None,
+                    error,
+                };
+                (Some(Output::Events(vec![rejection])), None)
+            }
+            Ok(command) => {
+                let mut journal = self.journal.lock().await;
+                if let Some(source) = source
+                    && journal.is_applied(source)
+                {
+                    return (self.recorded_output(&journal, source), None);
+                }
+                // Write-ahead: a command the journal cannot hold is never applied.
+                let command_id = match journal.append(&command, source) {
+                    Ok(sequence) => sequence,
+                    Err(e) => {
+                        error!("[{}] Failed to journal command: {}", self.symbol, e);
+                        let rejection = EngineEvent::CommandRejected {
+                            command: command.name().to_string(),
+                            order_id: command.order_id(),
+                            client_order_id: command.client_order_id().map(str::to_string),
+                            error: EngineError::JournalUnavailable {
+                                message: e.to_string(),
+                            },
+                        };
+                        return (Some(Output::Events(vec![rejection])), None);
+                    }
+                };
+                drop(journal);
+
+                debug!(
+                    "[{}] Processing {} {}",
+                    self.symbol,
+                    command.name(),
+                    command
+                        .order_id()
+                        .map(|id| id.to_string())
+                        .unwrap_or_default()
+                );
+                if let EngineCommand::SetMarketStatus { status } = &command {
+                    info!("[{}] Setting market status to {:?}", self.symbol, status);
+                }
+                // Lock the engine for the shortest time possible
+                let match_started = Instant::now();
+                let (trades, deltas, events) = command.apply(&mut *self.engine.lock().await);
+                let match_time = match_started.elapsed();
+                for event in &events {
+                    if let EngineEvent::CommandRejected { error, .. } = event {
+                        warn!("[{}] {}", self.symbol, error);
+                    }
+                }
+                debug!("[{}] Command processed.", self.symbol);
+
+                let recorded = RecordedOutput {
+                    trades,
+                    deltas,
+                    events,
+                };
+                // Only stream entries are redelivered, so only their outputs are kept.
+                if source.is_some()
+                    && let Err(e) = self
+                        .journal
+                        .lock()
+                        .await
+                        .record_output(command_id, &recorded)
+                {
+                    error!(
+                        "[{}] Failed to record the output of command {}: {}",
+                        self.symbol, command_id, e
+                    );
+                }
+                let output = self.command_output(command.name(), command_id, recorded);
+                let applied = Applied {
+                    sequence: command_id,
+                    match_time,
+                };
+                (Some(output), Some(applied))
+            }
+            Err(e) => {
+                warn!(
+                    "[{}] Failed to deserialize command from JSON '{}': {}",
+                    self.symbol, data_json, e
+                );
+                let rejection = reject_malformed_command(data_json, &e);
+                (Some(Output::Events(vec![rejection])), None)
             }
         }
-
-        println!(
-            "[{}] Redis orderbook initialized with {} bids and {} asks",
-            self.symbol,
-            snapshot.bids.len(),
-            snapshot.asks.len()
-        );
     }
 }
 
@@ -364,22 +582,42 @@ Usage Examples:
    Then listen for the response:
    SUBSCRIBE snapshot_response_123
 
-2. Query Redis orderbook directly:
-   # Get top 10 bids (highest prices first)
-   ZREVRANGE orderbook:bids:BTC_INR 0 9 WITHSCORES
+2. Query Redis orderbook directly. The sorted sets hold the exact price strings; the
+   `:levels` hashes map each of them to the level's quantity:
+   # Top 10 bid prices (highest first), then their quantities
+   ZREVRANGE orderbook:bids:BTC_INR 0 9
+   HMGET orderbook:bids:BTC_INR:levels <price> ...
 
-   # Get top 10 asks (lowest prices first)
-   ZRANGE orderbook:asks:BTC_INR 0 9 WITHSCORES
+   # Top 10 ask prices (lowest first)
+   ZRANGE orderbook:asks:BTC_INR 0 9
 
-   # Get all bids above 45000
-   ZREVRANGEBYSCORE orderbook:bids:BTC_INR -45000 -inf WITHSCORES
+   # All bid prices at or above 45000
+   ZRANGE orderbook:bids:BTC_INR +inf 45000 BYSCORE REV
 
-3. Subscribe to real-time deltas (unchanged):
+3. Subscribe to real-time book updates, one message per command with its trades and deltas
+   (or one message per delta with `--delta-format per-delta`):
    SUBSCRIBE orderbook:deltas:BTC_INR
 
+   Order-level engine events (e.g. post-only outcomes, rejections):
+   SUBSCRIBE orderbook:events:BTC_INR
+
+   Rejected orders and commands, with the reason, for the API layer:
+   LRANGE engine:rejections:BTC_INR 0 10
+
+   Execution reports (accepted, fills, cancels, rejections, expiries) for the DB worker:
+   LRANGE engine:processed_orders:BTC_INR 0 10
+
 4. Get recent trades (unchanged):
    LRANGE orderbook:trades:BTC_INR 0 10
 
 5. Get last traded price (unchanged):
    GET orderbook:ltp:BTC_INR
+
+6. With `Transport::Streams`, the same keys are streams and every entry carries its JSON in `data`:
+   XADD orderbook:orders:BTC_INR * data '{"command":"NewOrder","payload":{...}}'
+
+   The DB worker reads the processed streams through its own consumer group and acks each entry:
+   XGROUP CREATE engine:processed_trades:BTC_INR db-worker 0 MKSTREAM
+   XREADGROUP GROUP db-worker worker-1 COUNT 10 BLOCK 0 STREAMS engine:processed_trades:BTC_INR >
+   XACK engine:processed_trades:BTC_INR db-worker <entry id>
 */
@@ -0,0 +1,609 @@
+use crate::consumer::OrderConsumer;
+use crate::journal::{CommandSource, FsyncPolicy, Journal};
+use crate::matching_engine::*;
+use crate::snapshot::SnapshotStore;
+use crate::transport::*;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+fn temp_data_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("consumer-test-{}", Uuid::new_v4()))
+}
+
+fn consumer(
+    dir: &std::path::Path,
+    input: Box<dyn InputSource>,
+    output: Box<dyn OutputSink>,
+) -> OrderConsumer {
+    let (journal, _) = Journal::open(&dir.join("TEST.journal"), FsyncPolicy::Never, 0).unwrap();
+    OrderConsumer::new(
+        Arc::new(Mutex::new(MatchingEngine::new("TEST".to_string()))),
+        input,
+        output,
+        journal,
+        SnapshotStore::new(dir, "TEST", 1),
+        1_000,
+        "TEST".to_string(),
+    )
+}
+
+/// Stream entries from the orders stream, handed out in order; an id may be repeated to
+/// simulate a redelivery.
+struct StreamInput(VecDeque<InboundCommand>);
+
+impl StreamInput {
+    fn new(entries: Vec<(&str, String)>) -> Self {
+        StreamInput(
+            entries
+                .into_iter()
+                .map(|(id, data_json)| InboundCommand {
+                    origin: "orders".to_string(),
+                    data_json,
+                    source: Some(CommandSource {
+                        stream: "orders".to_string(),
+                        id: id.to_string(),
+                    }),
+                    admin: false,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[async_trait]
+impl InputSource for StreamInput {
+    async fn next_command(&mut self, _stop: &mut StopSignal) -> Option<InboundCommand> {
+        self.0.pop_front()
+    }
+}
+
+/// What a `RecordingSink` saw: the outputs it delivered and the entries it acknowledged.
+#[derive(Default)]
+struct Recorded {
+    published: Vec<Output>,
+    acknowledged: Vec<String>,
+}
+
+/// Fails the calls to `publish` numbered in `failing` (counting from 0). The publisher
+/// tries each output five times before giving up on it.
+struct RecordingSink {
+    failing: Range<usize>,
+    calls: usize,
+    recorded: Arc<std::sync::Mutex<Recorded>>,
+}
+
+impl RecordingSink {
+    fn new(failing: Range<usize>) -> (Self, Arc<std::sync::Mutex<Recorded>>) {
+        let recorded = Arc::new(std::sync::Mutex::new(Recorded::default()));
+        let sink = RecordingSink {
+            failing,
+            calls: 0,
+            recorded: recorded.clone(),
+        };
+        (sink, recorded)
+    }
+}
+
+#[async_trait]
+impl OutputSink for RecordingSink {
+    async fn publish(&mut self, output: &Output) -> io::Result<()> {
+        self.calls += 1;
+        if self.failing.contains(&(self.calls - 1)) {
+            return Err(io::Error::other("connection reset"));
+        }
+        self.recorded.lock().unwrap().published.push(output.clone());
+        Ok(())
+    }
+
+    async fn acknowledge(&mut self, source: &CommandSource) -> io::Result<()> {
+        self.recorded
+            .lock()
+            .unwrap()
+            .acknowledged
+            .push(source.id.clone());
+        Ok(())
+    }
+}
+
+fn new_order_json(side: &str, order_type: &str, price: &str, quantity: &str) -> String {
+    format!(
+        r#"{{"command":"NewOrder","payload":{{"id":"{}","user_id":"{}","order_type":"{}","side":"{}","price":"{}","quantity":"{}","timestamp":"2024-01-01T00:00:00Z"}}}}"#,
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        order_type,
+        side,
+        price,
+        quantity
+    )
+}
+
+#[tokio::test]
+async fn test_channel_transport_publishes_outputs_in_command_order() {
+    let dir = temp_data_dir();
+    let (commands, input) = channel_input(16);
+    let (sink, mut outputs) = channel_sink();
+    let consumer = consumer(&dir, Box::new(input), Box::new(sink));
+
+    commands
+        .send(new_order_json("Sell", "Limit", "100", "5"))
+        .await
+        .unwrap();
+    commands
+        .send(new_order_json("Buy", "Market", "0", "2"))
+        .await
+        .unwrap();
+    commands.send("not json".to_string()).await.unwrap();
+    commands
+        .send(r#"{"command":"SnapshotRequest","payload":{"response_channel":"reply"}}"#.to_string())
+        .await
+        .unwrap();
+    drop(commands);
+
+    // Returns once the input is exhausted and everything has been published.
+    consumer.run_consumer().await.unwrap();
+    drop(consumer);
+
+    let Some(Output::Command(first)) = outputs.recv().await else {
+        panic!("expected the resting order's output first");
+    };
+    assert_eq!(first.command, "NewOrder");
+    assert_eq!(first.command_id, 1);
+    assert_eq!(first.deltas[0].sequence, 1);
+
+    let Some(Output::Command(second)) = outputs.recv().await else {
+        panic!("expected the market order's output second");
+    };
+    assert_eq!(second.command_id, 2);
+    assert_eq!(second.trades[0].quantity, rust_decimal_macros::dec!(2));
+    assert_eq!(second.trades[0].sequence, 2);
+
+    let Some(Output::Events(events)) = outputs.recv().await else {
+        panic!("expected the malformed command's rejection third");
+    };
+    assert!(matches!(events[0], EngineEvent::CommandRejected { .. }));
+
+    let Some(Output::Snapshot {
+        response_channel,
+        snapshot,
+    }) = outputs.recv().await
+    else {
+        panic!("expected the snapshot last");
+    };
+    assert_eq!(response_channel, "reply");
+    assert_eq!(snapshot.sequence, 3);
+    assert_eq!(snapshot.asks[0].quantity, rust_decimal_macros::dec!(3));
+    assert!(outputs.recv().await.is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_line_input_and_json_lines_sink_run_offline() {
+    let dir = temp_data_dir();
+    fs::create_dir_all(&dir).unwrap();
+    let input_path = dir.join("commands.jsonl");
+    let output_path = dir.join("outputs.jsonl");
+    fs::write(
+        &input_path,
+        format!(
+            "{}\n\n{}\n",
+            new_order_json("Sell", "Limit", "100", "5"),
+            new_order_json("Buy", "Limit", "100", "1")
+        ),
+    )
+    .unwrap();
+
+    let input = LineInput::open(&input_path).await.unwrap();
+    let sink = JsonLinesSink::create(&output_path).await.unwrap();
+    consumer(&dir, Box::new(input), Box::new(sink))
+        .run_consumer()
+        .await
+        .unwrap();
+
+    let lines: Vec<serde_json::Value> = fs::read_to_string(&output_path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["output"], "Command");
+    assert_eq!(lines[1]["data"]["trades"][0]["price"], "100");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_stop_drains_and_snapshots_the_book() {
+    let dir = temp_data_dir();
+    let (commands, input) = channel_input(16);
+    let (sink, mut outputs) = channel_sink();
+    let consumer = Arc::new(consumer(&dir, Box::new(input), Box::new(sink)));
+
+    commands
+        .send(new_order_json("Sell", "Limit", "100", "5"))
+        .await
+        .unwrap();
+    let running = tokio::spawn({
+        let consumer = consumer.clone();
+        async move { consumer.run_consumer().await }
+    });
+    let Some(Output::Command(first)) = outputs.recv().await else {
+        panic!("expected the resting order's output");
+    };
+    assert_eq!(first.command_id, 1);
+
+    // The input is still open, so only the stop ends the consumer.
+    consumer.stop();
+    running.await.unwrap().unwrap();
+    assert!(outputs.try_recv().is_err());
+
+    let snapshot = SnapshotStore::new(&dir, "TEST", 1)
+        .load_latest()
+        .unwrap()
+        .expect("the book is snapshotted on stop");
+    assert_eq!(snapshot.journal_sequence, 1);
+    let mut restored = MatchingEngine::new("TEST".to_string());
+    restored.restore_state(snapshot.state);
+    assert_eq!(
+        restored.get_order_book_snapshot().asks[0].quantity,
+        rust_decimal_macros::dec!(5)
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_set_market_status_command_halts_trading() {
+    let dir = temp_data_dir();
+    let (commands, input) = channel_input(16);
+    let (sink, mut outputs) = channel_sink();
+    let consumer = consumer(&dir, Box::new(input), Box::new(sink));
+
+    commands
+        .send(r#"{"command":"SetMarketStatus","payload":{"status":"Halted"}}"#.to_string())
+        .await
+        .unwrap();
+    commands
+        .send(new_order_json("Sell", "Limit", "100", "5"))
+        .await
+        .unwrap();
+    drop(commands);
+    consumer.run_consumer().await.unwrap();
+    drop(consumer);
+
+    let Some(Output::Command(halt)) = outputs.recv().await else {
+        panic!("expected the status change's output first");
+    };
+    assert_eq!(halt.command, "SetMarketStatus");
+    assert_eq!(
+        halt.events,
+        vec![EngineEvent::MarketStatusChanged {
+            symbol: "TEST".to_string(),
+            previous: MarketStatus::Trading,
+            status: MarketStatus::Halted,
+        }]
+    );
+
+    let Some(Output::Command(order)) = outputs.recv().await else {
+        panic!("expected the refused order's output second");
+    };
+    assert!(order.deltas.is_empty());
+    assert!(order.events.iter().any(|event| matches!(
+        event,
+        EngineEvent::OrderRejected {
+            reason: RejectReason::MarketStatus {
+                status: MarketStatus::Halted
+            },
+            ..
+        }
+    )));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_failed_final_snapshot_is_reported() {
+    let dir = temp_data_dir();
+    let journal_path = dir.join("TEST.journal");
+    let (journal, _) = Journal::open(&journal_path, FsyncPolicy::Never, 0).unwrap();
+    let (commands, input) = channel_input(16);
+    let (sink, _outputs) = channel_sink();
+    // Snapshots cannot be written under a path that is a file.
+    let consumer = OrderConsumer::new(
+        Arc::new(Mutex::new(MatchingEngine::new("TEST".to_string()))),
+        Box::new(input),
+        Box::new(sink),
+        journal,
+        SnapshotStore::new(&journal_path, "TEST", 1),
+        1_000,
+        "TEST".to_string(),
+    );
+
+    commands
+        .send(new_order_json("Sell", "Limit", "100", "5"))
+        .await
+        .unwrap();
+    drop(commands);
+
+    assert!(consumer.run_consumer().await.is_err());
+    drop(consumer);
+    // The journal still holds the command for the next start to replay.
+    let (_, entries) = Journal::open(&journal_path, FsyncPolicy::Never, 0).unwrap();
+    assert_eq!(entries.len(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_redelivered_entry_publishes_its_recorded_output() {
+    let dir = temp_data_dir();
+    let resting = new_order_json("Sell", "Limit", "100", "5");
+    let taking = new_order_json("Buy", "Limit", "100", "2");
+    let input = StreamInput::new(vec![
+        ("1-0", resting),
+        ("2-0", taking.clone()),
+        ("2-0", taking),
+    ]);
+    let (sink, mut outputs) = channel_sink();
+    let consumer = consumer(&dir, Box::new(input), Box::new(sink));
+    consumer.run_consumer().await.unwrap();
+    drop(consumer);
+
+    let _resting = outputs.recv().await.unwrap();
+    let Some(Output::Command(first)) = outputs.recv().await else {
+        panic!("expected the taking order's output");
+    };
+    // The redelivery is not applied again, but its output is published as it was.
+    let Some(Output::Command(again)) = outputs.recv().await else {
+        panic!("expected the redelivered entry's recorded output");
+    };
+    assert_eq!(again.command_id, 2);
+    assert_eq!(again.trades[0].id, first.trades[0].id);
+    assert_eq!(again.trades[0].timestamp, first.trades[0].timestamp);
+    assert_eq!(again.deltas.len(), first.deltas.len());
+    assert_eq!(again.deltas[0].sequence, first.deltas[0].sequence);
+    assert_eq!(again.events, first.events);
+    assert!(outputs.recv().await.is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_failed_publish_is_not_acknowledged() {
+    let dir = temp_data_dir();
+    let input = StreamInput::new(vec![
+        ("1-0", new_order_json("Sell", "Limit", "100", "5")),
+        ("2-0", new_order_json("Sell", "Limit", "101", "5")),
+    ]);
+    // Every try at the first output fails.
+    let (sink, recorded) = RecordingSink::new(0..5);
+    // The book is left unsnapshotted for the redelivery, which is reported as a failure.
+    assert!(
+        consumer(&dir, Box::new(input), Box::new(sink))
+            .run_consumer()
+            .await
+            .is_err()
+    );
+
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.published.len(), 1);
+    assert_eq!(recorded.acknowledged, vec!["2-0".to_string()]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_snapshots_stop_at_the_last_published_command() {
+    let dir = temp_data_dir();
+    let journal_path = dir.join("TEST.journal");
+    let (journal, _) = Journal::open(&journal_path, FsyncPolicy::Never, 0).unwrap();
+    let input = StreamInput::new(vec![
+        ("1-0", new_order_json("Sell", "Limit", "100", "5")),
+        ("2-0", new_order_json("Buy", "Limit", "100", "2")),
+        ("3-0", new_order_json("Sell", "Limit", "101", "1")),
+    ]);
+    let (sink, recorded) = RecordingSink::new(1..6);
+    // Due for a snapshot after every command.
+    let consumer = OrderConsumer::new(
+        Arc::new(Mutex::new(MatchingEngine::new("TEST".to_string()))),
+        Box::new(input),
+        Box::new(sink),
+        journal,
+        SnapshotStore::new(&dir, "TEST", 1),
+        1,
+        "TEST".to_string(),
+    );
+    assert!(consumer.run_consumer().await.is_err());
+    drop(consumer);
+    assert_eq!(recorded.lock().unwrap().acknowledged, vec!["1-0", "3-0"]);
+
+    // The second command's output never went out, so nothing from it on is snapshotted...
+    let snapshot = SnapshotStore::new(&dir, "TEST", 1)
+        .load_latest()
+        .unwrap()
+        .unwrap();
+    assert_eq!(snapshot.journal_sequence, 1);
+
+    // ...and its redelivery after a restart still finds the output to publish.
+    let (journal, entries) = Journal::open(&journal_path, FsyncPolicy::Never, 1).unwrap();
+    assert_eq!(entries.len(), 2);
+    let source = CommandSource {
+        stream: "orders".to_string(),
+        id: "2-0".to_string(),
+    };
+    let entry = journal.find(&source).unwrap().unwrap();
+    assert_eq!(
+        entry.output.unwrap().trades[0].quantity,
+        rust_decimal_macros::dec!(2)
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_failed_publish_is_retried() {
+    let dir = temp_data_dir();
+    let input = StreamInput::new(vec![
+        ("1-0", new_order_json("Sell", "Limit", "100", "5")),
+        ("2-0", new_order_json("Sell", "Limit", "101", "5")),
+    ]);
+    // The connection comes back on the third try.
+    let (sink, recorded) = RecordingSink::new(0..2);
+    consumer(&dir, Box::new(input), Box::new(sink))
+        .run_consumer()
+        .await
+        .unwrap();
+
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.published.len(), 2);
+    assert_eq!(recorded.acknowledged, vec!["1-0", "2-0"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_undeliverable_list_output_does_not_stop_snapshots() {
+    let dir = temp_data_dir();
+    let journal_path = dir.join("TEST.journal");
+    let (journal, _) = Journal::open(&journal_path, FsyncPolicy::Never, 0).unwrap();
+    let (commands, input) = channel_input(16);
+    let (sink, recorded) = RecordingSink::new(0..5);
+    // Due for a snapshot after every command.
+    let consumer = OrderConsumer::new(
+        Arc::new(Mutex::new(MatchingEngine::new("TEST".to_string()))),
+        Box::new(input),
+        Box::new(sink),
+        journal,
+        SnapshotStore::new(&dir, "TEST", 1),
+        1,
+        "TEST".to_string(),
+    );
+    for price in ["100", "101"] {
+        commands
+            .send(new_order_json("Sell", "Limit", price, "5"))
+            .await
+            .unwrap();
+    }
+    drop(commands);
+    consumer.run_consumer().await.unwrap();
+    drop(consumer);
+
+    // Nothing would ever publish the first output again, so it is dropped and the book
+    // is snapshotted past it.
+    assert_eq!(recorded.lock().unwrap().published.len(), 1);
+    let snapshot = SnapshotStore::new(&dir, "TEST", 1)
+        .load_latest()
+        .unwrap()
+        .unwrap();
+    assert_eq!(snapshot.journal_sequence, 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_refused_amend_names_the_client_order_id() {
+    let dir = temp_data_dir();
+    let (commands, input) = channel_input(16);
+    let (sink, mut outputs) = channel_sink();
+    let consumer = consumer(&dir, Box::new(input), Box::new(sink));
+
+    let order_id = Uuid::new_v4();
+    commands
+        .send(format!(
+            r#"{{"command":"NewOrder","payload":{{"id":"{}","user_id":"{}","order_type":"Limit","side":"Sell","price":"100","quantity":"5","timestamp":"2024-01-01T00:00:00Z","client_order_id":"client-3"}}}}"#,
+            order_id,
+            Uuid::new_v4()
+        ))
+        .await
+        .unwrap();
+    commands
+        .send(format!(
+            r#"{{"command":"ModifyOrder","payload":{{"order_id":"{}","new_quantity":"0"}}}}"#,
+            order_id
+        ))
+        .await
+        .unwrap();
+    drop(commands);
+    consumer.run_consumer().await.unwrap();
+    drop(consumer);
+
+    let _resting = outputs.recv().await.unwrap();
+    let Some(Output::Command(amend)) = outputs.recv().await else {
+        panic!("expected the amend's output");
+    };
+    assert!(amend.events.iter().any(|event| matches!(
+        event,
+        EngineEvent::CommandRejected {
+            client_order_id: Some(client_order_id),
+            ..
+        } if client_order_id == "client-3"
+    )));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_market_status_only_changes_from_the_admin_queue() {
+    let dir = temp_data_dir();
+    let halt = r#"{"command":"SetMarketStatus","payload":{"status":"Halted"}}"#;
+    let mut input = StreamInput::new(vec![
+        ("1-0", halt.to_string()),
+        ("2-0", new_order_json("Sell", "Limit", "100", "5")),
+    ]);
+    input.0.push_back(InboundCommand {
+        origin: "admin".to_string(),
+        data_json: halt.to_string(),
+        source: Some(CommandSource {
+            stream: "admin".to_string(),
+            id: "1-0".to_string(),
+        }),
+        admin: true,
+    });
+    let (sink, recorded) = RecordingSink::new(0..0);
+    consumer(&dir, Box::new(input), Box::new(sink))
+        .run_consumer()
+        .await
+        .unwrap();
+
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.published.len(), 3);
+    let Output::Events(refused) = &recorded.published[0] else {
+        panic!("expected the refusal of the status change from the orders stream");
+    };
+    assert_eq!(
+        refused[0],
+        EngineEvent::CommandRejected {
+            command: "SetMarketStatus".to_string(),
+            order_id: None,
+            client_order_id: None,
+            error: EngineError::AdminOnly {
+                origin: "orders".to_string(),
+            },
+        }
+    );
+    // The market kept trading, and the refused entry is still acknowledged.
+    let Output::Command(order) = &recorded.published[1] else {
+        panic!("expected the order's output");
+    };
+    assert_eq!(order.deltas.len(), 1);
+    let Output::Command(halted) = &recorded.published[2] else {
+        panic!("expected the admin stream's status change");
+    };
+    assert!(matches!(
+        halted.events[0],
+        EngineEvent::MarketStatusChanged {
+            status: MarketStatus::Halted,
+            ..
+        }
+    ));
+    assert_eq!(recorded.acknowledged, vec!["1-0", "2-0", "1-0"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
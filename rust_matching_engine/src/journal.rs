@@ -0,0 +1,495 @@
+// journal.rs
+use crate::command::EngineCommand;
+use crate::matching_engine::{EngineEvent, MatchingEngine, OrderBookDelta, Trade};
+use log::warn;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// When the journal forces appended commands to stable storage. Written as `always`,
+/// `every:<n>` or `never`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum FsyncPolicy {
+    /// fsync after every command. Nothing the engine has processed can be lost.
+    Always,
+    /// fsync once every `n` commands. A crash can lose up to `n - 1` commands.
+    EveryN(u32),
+    /// Never fsync explicitly and leave flushing to the OS.
+    Never,
+}
+
+impl fmt::Display for FsyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsyncPolicy::Always => write!(f, "always"),
+            FsyncPolicy::EveryN(n) => write!(f, "every:{}", n),
+            FsyncPolicy::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "never" => Ok(FsyncPolicy::Never),
+            other => other
+                .strip_prefix("every:")
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .map(FsyncPolicy::EveryN)
+                .ok_or(format!(
+                    "unknown fsync policy '{}' (always, every:<n> with n >= 1, or never)",
+                    s
+                )),
+        }
+    }
+}
+
+impl TryFrom<String> for FsyncPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<FsyncPolicy> for String {
+    fn from(policy: FsyncPolicy) -> Self {
+        policy.to_string()
+    }
+}
+
+/// The Redis stream entry a command was read from. Streams redeliver unacknowledged
+/// entries, so this is what lets the engine recognise a command it has already applied.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CommandSource {
+    pub stream: String,
+    pub id: String,
+}
+
+/// A journaled command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<CommandSource>,
+    pub command: EngineCommand,
+    /// What applying the command produced, if it was recorded. Kept on a line of its own.
+    #[serde(skip)]
+    pub output: Option<RecordedOutput>,
+}
+
+/// What applying a command read from a stream entry produced. Recorded after the command
+/// and before any of it is published, so an entry redelivered after a crash is published
+/// again exactly as the first time, with the same trade ids and timestamps.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordedOutput {
+    pub trades: Vec<Trade>,
+    pub deltas: Vec<OrderBookDelta>,
+    pub events: Vec<EngineEvent>,
+}
+
+/// A journal line holding the output recorded for the command with the same sequence.
+/// Told apart from a command line by its `output` field.
+#[derive(Serialize)]
+struct OutputLine<'a> {
+    sequence: u64,
+    output: &'a RecordedOutput,
+}
+
+/// `OutputLine` as it is read back.
+#[derive(Deserialize)]
+struct RecordedOutputLine {
+    sequence: u64,
+    output: RecordedOutput,
+}
+
+/// Where a stream command's lines start in the file, so a redelivered entry is looked up
+/// without reading the whole journal.
+#[derive(Debug, Clone, Copy)]
+struct LineOffsets {
+    entry: u64,
+    output: Option<u64>,
+}
+
+/// Just enough of a line to tell which kind it is.
+#[derive(Deserialize)]
+struct LineKind {
+    #[serde(default)]
+    output: Option<IgnoredAny>,
+}
+
+/// Append-only, newline-delimited JSON log of every command applied to one market's engine.
+///
+/// Commands are written before the engine sees them, so replaying the file into an empty
+/// engine (or one restored from a snapshot) rebuilds the book exactly as it was.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    // Length of the file through the last complete line.
+    len: u64,
+    // Set when a failed write could not be rolled back; every later append fails.
+    damaged: bool,
+    policy: FsyncPolicy,
+    next_sequence: u64,
+    entry_count: u64,
+    unsynced: u32,
+    // Last applied entry id per inbound stream.
+    stream_positions: BTreeMap<String, String>,
+    // The lines of every stream command in the file, by sequence, and the sequence of
+    // each such command by the entry it was read from.
+    source_lines: BTreeMap<u64, LineOffsets>,
+    sources: HashMap<CommandSource, u64>,
+}
+
+impl Journal {
+    /// Opens (or creates) the journal at `path` and returns it together with the entries
+    /// recorded after `snapshot_sequence`, the last command already covered by a snapshot
+    /// (0 without one). Numbering continues after whichever is higher.
+    ///
+    /// A final line cut short by a crash is dropped and truncated away; a corrupt line
+    /// anywhere else is an error.
+    pub fn open(
+        path: &Path,
+        policy: FsyncPolicy,
+        snapshot_sequence: u64,
+    ) -> io::Result<(Self, Vec<JournalEntry>)> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let (entries, offsets, valid_len) = read_entries(path)?;
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.set_len(valid_len)?;
+
+        let next_sequence = entries
+            .last()
+            .map_or(1, |entry| entry.sequence + 1)
+            .max(snapshot_sequence + 1);
+        let mut journal = Journal {
+            path: path.to_path_buf(),
+            file,
+            len: valid_len,
+            damaged: false,
+            policy,
+            next_sequence,
+            entry_count: entries.len() as u64,
+            unsynced: 0,
+            stream_positions: BTreeMap::new(),
+            source_lines: BTreeMap::new(),
+            sources: HashMap::new(),
+        };
+        journal.index_sources(&entries, &offsets);
+        for source in entries.iter().filter_map(|entry| entry.source.as_ref()) {
+            journal.advance_stream_position(&source.stream, &source.id);
+        }
+        let entries = entries
+            .into_iter()
+            .filter(|entry| entry.sequence > snapshot_sequence)
+            .collect();
+        Ok((journal, entries))
+    }
+
+    /// Appends `command` and returns its sequence number.
+    pub fn append(
+        &mut self,
+        command: &EngineCommand,
+        source: Option<&CommandSource>,
+    ) -> io::Result<u64> {
+        let entry = JournalEntry {
+            sequence: self.next_sequence,
+            source: source.cloned(),
+            command: command.clone(),
+            output: None,
+        };
+        let offset = self.len;
+        self.write_line(&entry)?;
+        self.next_sequence += 1;
+        self.entry_count += 1;
+        if let Some(source) = source {
+            self.advance_stream_position(&source.stream, &source.id);
+            let offsets = LineOffsets {
+                entry: offset,
+                output: None,
+            };
+            self.source_lines.insert(entry.sequence, offsets);
+            self.sources.insert(source.clone(), entry.sequence);
+        }
+        Ok(entry.sequence)
+    }
+
+    /// Records what applying the command journaled as `sequence` produced. Must succeed
+    /// before any of it is published for the output to be republished identically.
+    pub fn record_output(&mut self, sequence: u64, output: &RecordedOutput) -> io::Result<()> {
+        let offset = self.len;
+        self.write_line(&OutputLine { sequence, output })?;
+        if let Some(offsets) = self.source_lines.get_mut(&sequence) {
+            offsets.output = Some(offset);
+        }
+        Ok(())
+    }
+
+    /// The journaled command read from `source`, with its recorded output, if the journal
+    /// still holds it. Reads only that command's lines.
+    pub fn find(&self, source: &CommandSource) -> io::Result<Option<JournalEntry>> {
+        let Some(offsets) = self
+            .sources
+            .get(source)
+            .and_then(|sequence| self.source_lines.get(sequence))
+        else {
+            return Ok(None);
+        };
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut entry: JournalEntry = read_line_at(&mut reader, offsets.entry)?;
+        if let Some(offset) = offsets.output {
+            let line: RecordedOutputLine = read_line_at(&mut reader, offset)?;
+            entry.output = Some(line.output);
+        }
+        Ok(Some(entry))
+    }
+
+    /// Rebuilds the index of stream commands from what was read from the file.
+    fn index_sources(&mut self, entries: &[JournalEntry], offsets: &[LineOffsets]) {
+        self.source_lines.clear();
+        self.sources.clear();
+        for (entry, offsets) in entries.iter().zip(offsets) {
+            if let Some(source) = &entry.source {
+                self.source_lines.insert(entry.sequence, *offsets);
+                self.sources.insert(source.clone(), entry.sequence);
+            }
+        }
+    }
+
+    /// Writes one line and syncs it if the policy says so. On failure the file is cut back
+    /// to where it was, so a command reported as not journaled is never replayed.
+    fn write_line(&mut self, line: &impl Serialize) -> io::Result<()> {
+        let line = json_line(line)?;
+        if self.damaged {
+            return Err(io::Error::other(
+                "journal holds a partial write that could not be removed; restart to recover",
+            ));
+        }
+        let due = match self.policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::EveryN(n) => self.unsynced + 1 >= n.max(1),
+            FsyncPolicy::Never => false,
+        };
+        let written = self
+            .file
+            .write_all(&line)
+            .and_then(|_| if due { self.sync() } else { Ok(()) });
+        if let Err(e) = written {
+            if let Err(rollback) = self.file.set_len(self.len) {
+                warn!(
+                    "Journal '{}': failed to remove a partial write: {}",
+                    self.path.display(),
+                    rollback
+                );
+                self.damaged = true;
+            }
+            return Err(e);
+        }
+        self.len += line.len() as u64;
+        if !due {
+            self.unsynced += 1;
+        }
+        Ok(())
+    }
+
+    /// True if a command from this stream entry (or a later one) has already been journaled.
+    pub fn is_applied(&self, source: &CommandSource) -> bool {
+        self.stream_positions
+            .get(&source.stream)
+            .is_some_and(|last| stream_id_order(&source.id) <= stream_id_order(last))
+    }
+
+    pub fn stream_positions(&self) -> &BTreeMap<String, String> {
+        &self.stream_positions
+    }
+
+    /// Folds in positions recorded by a snapshot, keeping the later id for every stream.
+    pub fn merge_stream_positions(&mut self, positions: &BTreeMap<String, String>) {
+        for (stream, id) in positions {
+            self.advance_stream_position(stream, id);
+        }
+    }
+
+    fn advance_stream_position(&mut self, stream: &str, id: &str) {
+        let position = self.stream_positions.entry(stream.to_string()).or_default();
+        if position.is_empty() || stream_id_order(id) > stream_id_order(position) {
+            *position = id.to_string();
+        }
+    }
+
+    /// Drops every entry up to and including `sequence` once a snapshot covers them.
+    /// The remaining entries are rewritten to a new file that replaces the old one.
+    pub fn truncate_through(&mut self, sequence: u64) -> io::Result<()> {
+        self.sync()?;
+        let (entries, _, _) = read_entries(&self.path)?;
+        let tmp_path = self.path.with_extension("journal.tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        let kept: Vec<JournalEntry> = entries
+            .into_iter()
+            .filter(|entry| entry.sequence > sequence)
+            .collect();
+        let mut offsets = Vec::with_capacity(kept.len());
+        let mut len = 0;
+        for entry in &kept {
+            let line = json_line(entry)?;
+            tmp.write_all(&line)?;
+            let mut entry_offsets = LineOffsets {
+                entry: len,
+                output: None,
+            };
+            len += line.len() as u64;
+            if let Some(output) = &entry.output {
+                let line = json_line(&OutputLine {
+                    sequence: entry.sequence,
+                    output,
+                })?;
+                tmp.write_all(&line)?;
+                entry_offsets.output = Some(len);
+                len += line.len() as u64;
+            }
+            offsets.push(entry_offsets);
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.len = self.file.metadata()?.len();
+        self.entry_count = kept.len() as u64;
+        self.index_sources(&kept, &offsets);
+        Ok(())
+    }
+
+    /// Forces everything appended so far to disk, regardless of the policy.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sequence number of the last command written, or of the snapshot the journal
+    /// was opened after.
+    pub fn last_sequence(&self) -> u64 {
+        self.next_sequence - 1
+    }
+
+    /// Number of entries in the file, i.e. written since the last truncation.
+    pub fn entry_count(&self) -> u64 {
+        self.entry_count
+    }
+}
+
+fn json_line(value: &impl Serialize) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Stream ids are `<milliseconds>-<sequence>`; compare them numerically.
+fn stream_id_order(id: &str) -> (u64, u64) {
+    let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+    (ms.parse().unwrap_or(0), seq.parse().unwrap_or(0))
+}
+
+/// Reads the line starting at byte `offset`.
+fn read_line_at<T: for<'de> Deserialize<'de>>(
+    reader: &mut BufReader<File>,
+    offset: u64,
+) -> io::Result<T> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("corrupt journal entry at byte {}: {}", offset, e),
+        )
+    })
+}
+
+/// Reads every intact entry of the journal at `path`, with the outputs recorded for them,
+/// where their lines start, and the byte length they span.
+fn read_entries(path: &Path) -> io::Result<(Vec<JournalEntry>, Vec<LineOffsets>, u64)> {
+    let mut entries = Vec::new();
+    let mut offsets: Vec<LineOffsets> = Vec::new();
+    let mut valid_len = 0u64;
+    if !path.exists() {
+        return Ok((entries, offsets, valid_len));
+    }
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        // Only the last line can be missing its newline: a write cut short by a crash.
+        if !line.ends_with('\n') {
+            warn!(
+                "Journal '{}': dropping incomplete last entry",
+                path.display()
+            );
+            break;
+        }
+        let corrupt = |e: serde_json::Error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("corrupt journal entry at byte {}: {}", valid_len, e),
+            )
+        };
+        let kind: LineKind = serde_json::from_str(&line).map_err(corrupt)?;
+        if kind.output.is_some() {
+            let RecordedOutputLine { sequence, output } =
+                serde_json::from_str(&line).map_err(corrupt)?;
+            if let Some(index) = entries.iter().rposition(|e| e.sequence == sequence) {
+                entries[index].output = Some(output);
+                offsets[index].output = Some(valid_len);
+            }
+        } else {
+            entries.push(serde_json::from_str(&line).map_err(corrupt)?);
+            offsets.push(LineOffsets {
+                entry: valid_len,
+                output: None,
+            });
+        }
+        valid_len += read as u64;
+    }
+    Ok((entries, offsets, valid_len))
+}
+
+/// Re-applies journaled commands to `engine`. What they produce was published before the
+/// restart, or is published from the recorded output when a stream redelivers the entry,
+/// so it is discarded. The one exception is returned: the output of stream commands whose
+/// output was never recorded because of a crash, which the caller records in their place.
+pub fn replay(engine: &mut MatchingEngine, entries: &[JournalEntry]) -> Vec<(u64, RecordedOutput)> {
+    let mut unrecorded = Vec::new();
+    for entry in entries {
+        let (trades, deltas, events) = entry.command.apply(engine);
+        if entry.source.is_some() && entry.output.is_none() {
+            let output = RecordedOutput {
+                trades,
+                deltas,
+                events,
+            };
+            unrecorded.push((entry.sequence, output));
+        }
+    }
+    unrecorded
+}
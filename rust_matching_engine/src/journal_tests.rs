@@ -0,0 +1,301 @@
+use crate::command::EngineCommand;
+use crate::journal::*;
+use crate::matching_engine::*;
+use chrono::Utc;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+fn temp_journal_path() -> PathBuf {
+    std::env::temp_dir()
+        .join(format!("journal-test-{}", Uuid::new_v4()))
+        .join("TEST.journal")
+}
+
+fn limit_order(side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+    Order {
+        id: Uuid::new_v4(),
+        user_id: Uuid::new_v4(),
+        order_type: OrderType::Limit,
+        side,
+        price,
+        quantity,
+        timestamp: Utc::now(),
+        time_in_force: TimeInForce::Gtc,
+        stop_price: None,
+        post_only: None,
+        self_trade_prevention: None,
+        client_order_id: None,
+    }
+}
+
+fn journal_entries(path: &Path) -> Vec<JournalEntry> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_journal_replay_rebuilds_book() {
+    let path = temp_journal_path();
+    let bid = limit_order(OrderSide::Buy, dec!(99), dec!(5));
+    let ask = limit_order(OrderSide::Sell, dec!(101), dec!(3));
+    let crossing = limit_order(OrderSide::Buy, dec!(101), dec!(1));
+    let commands = vec![
+        EngineCommand::NewOrder(bid.clone()),
+        EngineCommand::NewOrder(ask.clone()),
+        EngineCommand::NewOrder(crossing),
+        EngineCommand::ModifyOrder {
+            order_id: bid.id,
+            new_price: Some(dec!(100)),
+            new_quantity: None,
+        },
+        EngineCommand::CancelOrder { order_id: ask.id },
+    ];
+
+    {
+        let (mut journal, entries) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+        assert!(entries.is_empty());
+        for command in &commands {
+            journal.append(command, None).unwrap();
+        }
+    }
+
+    let (_, entries) = Journal::open(&path, FsyncPolicy::Never, 0).unwrap();
+    let sequences: Vec<u64> = entries.iter().map(|e| e.sequence).collect();
+    assert_eq!(sequences, vec![1, 2, 3, 4, 5]);
+
+    let mut restored = MatchingEngine::new("TEST".to_string());
+    // Commands that did not come from a stream have no output to record.
+    assert!(replay(&mut restored, &entries).is_empty());
+    let snapshot = restored.get_order_book_snapshot();
+    assert_eq!(snapshot.bids.len(), 1);
+    assert_eq!(snapshot.bids[0].price, dec!(100));
+    assert_eq!(snapshot.bids[0].quantity, dec!(5));
+    assert!(snapshot.asks.is_empty());
+    assert_eq!(snapshot.last_traded_price, Some(dec!(101)));
+    assert!(restored.take_events().is_empty());
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_journal_drops_torn_last_entry() {
+    let path = temp_journal_path();
+    let order = limit_order(OrderSide::Buy, dec!(99), dec!(5));
+    {
+        let (mut journal, _) = Journal::open(&path, FsyncPolicy::EveryN(10), 0).unwrap();
+        journal
+            .append(&EngineCommand::NewOrder(order.clone()), None)
+            .unwrap();
+        journal.sync().unwrap();
+    }
+    let intact_len = fs::metadata(&path).unwrap().len();
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(br#"{"sequence":2,"command":{"comm"#)
+        .unwrap();
+
+    let (mut journal, entries) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(fs::metadata(&path).unwrap().len(), intact_len);
+
+    // Appending carries on from the last intact entry.
+    let sequence = journal
+        .append(&EngineCommand::CancelOrder { order_id: order.id }, None)
+        .unwrap();
+    assert_eq!(sequence, 2);
+    assert_eq!(journal_entries(&path).len(), 2);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_journal_rejects_corrupt_entry() {
+    let path = temp_journal_path();
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, "not json\n{}\n").unwrap();
+
+    let err = Journal::open(&path, FsyncPolicy::Always, 0).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_journal_truncation_after_snapshot() {
+    let path = temp_journal_path();
+    let orders: Vec<Order> = (0..3)
+        .map(|_| limit_order(OrderSide::Buy, dec!(99), dec!(1)))
+        .collect();
+    {
+        let (mut journal, _) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+        for order in &orders {
+            journal
+                .append(&EngineCommand::NewOrder(order.clone()), None)
+                .unwrap();
+        }
+        journal.truncate_through(2).unwrap();
+        assert_eq!(journal.entry_count(), 1);
+        assert_eq!(journal.last_sequence(), 3);
+        journal.truncate_through(3).unwrap();
+        assert_eq!(journal.entry_count(), 0);
+    }
+
+    // Numbering continues after the snapshot even though the file is empty.
+    let (mut journal, entries) = Journal::open(&path, FsyncPolicy::Always, 3).unwrap();
+    assert!(entries.is_empty());
+    let sequence = journal
+        .append(
+            &EngineCommand::CancelOrder {
+                order_id: orders[0].id,
+            },
+            None,
+        )
+        .unwrap();
+    assert_eq!(sequence, 4);
+
+    // Entries a snapshot already covers are not handed back for replay.
+    let (_, entries) = Journal::open(&path, FsyncPolicy::Always, 4).unwrap();
+    assert!(entries.is_empty());
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_journal_tracks_stream_positions() {
+    let path = temp_journal_path();
+    let source = |id: &str| CommandSource {
+        stream: "orders".to_string(),
+        id: id.to_string(),
+    };
+    let order = limit_order(OrderSide::Buy, dec!(99), dec!(1));
+    {
+        let (mut journal, _) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+        assert!(!journal.is_applied(&source("5-0")));
+        journal
+            .append(
+                &EngineCommand::NewOrder(order.clone()),
+                Some(&source("1700000000000-9")),
+            )
+            .unwrap();
+        // Ids compare numerically, not as strings.
+        assert!(journal.is_applied(&source("1700000000000-9")));
+        assert!(journal.is_applied(&source("999999999999-20")));
+        assert!(!journal.is_applied(&source("1700000000000-10")));
+    }
+
+    // Positions are rebuilt from the file and merged with a snapshot's, keeping the latest.
+    let (mut journal, _) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+    assert!(journal.is_applied(&source("1700000000000-9")));
+    let mut from_snapshot = BTreeMap::new();
+    from_snapshot.insert("orders".to_string(), "1600000000000-0".to_string());
+    from_snapshot.insert("cancels".to_string(), "42-1".to_string());
+    journal.merge_stream_positions(&from_snapshot);
+    assert_eq!(journal.stream_positions()["orders"], "1700000000000-9");
+    assert_eq!(journal.stream_positions()["cancels"], "42-1");
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_journal_keeps_recorded_outputs() {
+    let path = temp_journal_path();
+    let source = |id: &str| CommandSource {
+        stream: "orders".to_string(),
+        id: id.to_string(),
+    };
+    let bid = limit_order(OrderSide::Buy, dec!(99), dec!(5));
+    let ask = limit_order(OrderSide::Sell, dec!(99), dec!(2));
+    let mut engine = MatchingEngine::new("TEST".to_string());
+    {
+        let (mut journal, _) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+        for (command, id) in [
+            (EngineCommand::NewOrder(bid.clone()), "1-0"),
+            (EngineCommand::NewOrder(ask.clone()), "2-0"),
+        ] {
+            let sequence = journal.append(&command, Some(&source(id))).unwrap();
+            let (trades, deltas, events) = command.apply(&mut engine);
+            let output = RecordedOutput {
+                trades,
+                deltas,
+                events,
+            };
+            journal.record_output(sequence, &output).unwrap();
+        }
+        // Output lines are not entries.
+        assert_eq!(journal.entry_count(), 2);
+    }
+
+    let (mut journal, entries) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+    assert_eq!(entries.len(), 2);
+    let trade = &entries[1].output.as_ref().unwrap().trades[0];
+    assert_eq!(trade.quantity, dec!(2));
+    assert_eq!(trade.maker_order_id, bid.id);
+
+    // Replaying entries whose output is recorded hands nothing back to record.
+    let mut restored = MatchingEngine::new("TEST".to_string());
+    assert!(replay(&mut restored, &entries).is_empty());
+
+    // The output is found by the exact stream entry and survives truncation.
+    let found = journal.find(&source("2-0")).unwrap().unwrap();
+    assert_eq!(found.sequence, 2);
+    assert_eq!(found.output.unwrap().trades[0].id, trade.id);
+    assert!(journal.find(&source("3-0")).unwrap().is_none());
+    journal.truncate_through(1).unwrap();
+    assert!(journal.find(&source("1-0")).unwrap().is_none());
+    let found = journal.find(&source("2-0")).unwrap().unwrap();
+    assert_eq!(found.output.unwrap().trades[0].id, trade.id);
+
+    // Lines appended after the rewrite are found where they were written.
+    let cancel = EngineCommand::CancelOrder { order_id: bid.id };
+    let sequence = journal.append(&cancel, Some(&source("3-0"))).unwrap();
+    let (trades, deltas, events) = cancel.apply(&mut engine);
+    let output = RecordedOutput {
+        trades,
+        deltas,
+        events,
+    };
+    journal.record_output(sequence, &output).unwrap();
+    let found = journal.find(&source("3-0")).unwrap().unwrap();
+    assert_eq!(found.sequence, 3);
+    assert_eq!(found.output.unwrap().deltas.len(), output.deltas.len());
+    let found = journal.find(&source("2-0")).unwrap().unwrap();
+    assert_eq!(found.output.unwrap().trades[0].id, trade.id);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_journal_replay_returns_unrecorded_outputs() {
+    let path = temp_journal_path();
+    let order = limit_order(OrderSide::Buy, dec!(99), dec!(5));
+    {
+        let (mut journal, _) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+        let source = CommandSource {
+            stream: "orders".to_string(),
+            id: "1-0".to_string(),
+        };
+        // A crash between journaling the command and recording its output.
+        journal
+            .append(&EngineCommand::NewOrder(order.clone()), Some(&source))
+            .unwrap();
+    }
+
+    let (_, entries) = Journal::open(&path, FsyncPolicy::Always, 0).unwrap();
+    let mut restored = MatchingEngine::new("TEST".to_string());
+    let unrecorded = replay(&mut restored, &entries);
+    assert_eq!(unrecorded.len(), 1);
+    assert_eq!(unrecorded[0].0, 1);
+    assert_eq!(unrecorded[0].1.deltas.len(), 1);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
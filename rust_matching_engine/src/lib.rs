@@ -0,0 +1,39 @@
+// lib.rs
+//! Price-time priority matching engine for off-chain order books.
+//!
+//! The engine itself (`MatchingEngine`, its orders, trades, deltas and events), the
+//! command format, the journal and snapshots have no async or Redis dependencies. The
+//! consumer that runs a market, with its transport traits and the in-process and offline
+//! transports, is behind the `consumer` feature; the Redis transport is behind `redis`.
+//! Both are on by default through `cli`, which also brings in what only the binary needs.
+
+pub mod command;
+pub mod journal;
+pub mod matching_engine;
+pub mod snapshot;
+
+#[cfg(feature = "consumer")]
+pub mod consumer;
+#[cfg(feature = "redis")]
+pub mod redis_transport;
+#[cfg(feature = "consumer")]
+pub mod transport;
+
+#[cfg(all(test, feature = "consumer"))]
+mod consumer_tests;
+#[cfg(test)]
+mod journal_tests;
+#[cfg(test)]
+mod matching_engine_tests;
+#[cfg(all(test, feature = "redis"))]
+mod redis_transport_tests;
+#[cfg(test)]
+mod snapshot_tests;
+
+pub use command::EngineCommand;
+pub use matching_engine::{
+    DeltaAction, EngineError, EngineEvent, EngineState, ExecutionReport, ExecutionStatus, FeeRates,
+    FeeSchedule, MarketRules, MarketStatus, MatchingEngine, Order, OrderBookDelta, OrderBookLevel,
+    OrderBookSnapshot, OrderSide, OrderType, PostOnlyMode, PostOnlyOutcome, RejectReason,
+    SelfTradePrevention, TimeInForce, Trade,
+};
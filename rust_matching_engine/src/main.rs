@@ -1,171 +1,231 @@
 // main.rs
-mod consumer;
-mod matching_engine;
-mod matching_engine_tests;
-
-use consumer::OrderConsumer;
-use matching_engine::MatchingEngine;
-use serde::Deserialize;
-use std::fs;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-const REDIS_URL: &str = "redis://127.0.0.1:6379";
-const CONFIG_FILE_PATH: &str = "../markets.json";
-
-#[derive(Deserialize, Debug, Clone)]
-struct TradingPairConfig {
-    symbol: String,
-    base_asset: String,
-    quote_asset: String,
-    enabled: bool,
-    #[allow(dead_code)]
-    description: String,
+mod config;
+#[cfg(test)]
+mod config_tests;
+mod markets;
+#[cfg(test)]
+mod markets_tests;
+mod shutdown;
+
+use config::{Config, USAGE};
+use log::{error, info, warn};
+use markets::{MarketIo, Markets, load_markets, open_market};
+use rust_matching_engine::redis_transport::RedisKeys;
+use shutdown::ShutdownSignals;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Writes log records as plain lines: errors and warnings to stderr, the rest to stdout.
+struct StdLogger;
+
+impl log::Log for StdLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() <= log::Level::Warn {
+            eprintln!("{}", record.args());
+        } else {
+            println!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
 }
 
+static LOGGER: StdLogger = StdLogger;
+
+/// How often markets whose consumer exited are restarted when the markets file is not
+/// watched.
+const EXITED_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 #[tokio::main]
-async fn main() {
-    println!("Starting Matching Engine Application");
+async fn main() -> ExitCode {
+    let (config, options) =
+        match Config::load(std::env::args().skip(1), |name| std::env::var(name).ok()) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::from(2);
+            }
+        };
+    if options.help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    if let Err(e) = log::set_logger(&LOGGER) {
+        eprintln!("Failed to install logger: {}", e);
+    }
+    log::set_max_level(config.log_level);
 
-    let config_content = match fs::read_to_string(CONFIG_FILE_PATH) {
-        Ok(content) => content,
+    let trading_pair_configs = match load_markets(&config) {
+        Ok(pairs) => pairs,
         Err(e) => {
-            eprintln!("Failed to read config file '{}': {}", CONFIG_FILE_PATH, e);
-            return;
+            eprintln!("{}", e);
+            return ExitCode::from(2);
         }
     };
+    let symbols: Vec<&str> = trading_pair_configs
+        .iter()
+        .map(|pair| pair.symbol.as_str())
+        .collect();
+    if let Err(e) = config.validate_markets(&symbols) {
+        eprintln!("{}", e);
+        return ExitCode::from(2);
+    }
 
-    let trading_pair_configs: Vec<TradingPairConfig> = match serde_json::from_str(&config_content) {
-        Ok(configs) => configs,
-        Err(e) => {
-            eprintln!("Failed to parse config file '{}': {}", CONFIG_FILE_PATH, e);
-            return;
+    if options.print_config {
+        match toml::to_string_pretty(&config) {
+            Ok(dump) => print!("{}", dump),
+            Err(e) => eprintln!("Failed to print config: {}", e),
         }
-    };
-
-    let mut consumer_handles = Vec::new();
+        return ExitCode::SUCCESS;
+    }
 
-    for config in trading_pair_configs {
-        if !config.enabled {
-            println!("Skipping disabled trading pair: {}", config.symbol);
-            continue;
+    info!("Starting Matching Engine Application");
+    let mut signals = match ShutdownSignals::listen() {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to listen for shutdown signals: {}", e);
+            return ExitCode::FAILURE;
         }
+    };
 
-        let symbol_key_part = format!(
-            "{}_{}",
-            config.base_asset.to_uppercase(),
-            config.quote_asset.to_uppercase()
-        );
-        let order_queue_name = format!("orderbook:orders:{}", symbol_key_part);
-        let trade_queue_name = format!("orderbook:trades:{}", symbol_key_part);
-        let cancel_queue_name = format!("orderbook:cancel:{}", symbol_key_part);
-        // ** --- These two queues are used to seed the db with the trades/orders happen by the matching engine.
-        let processed_orders_queue_name = format!("engine:processed_orders:{}", symbol_key_part);
-        let processed_trades_queue_name = format!("engine:processed_trades:{}", symbol_key_part);
-        // ** --- These two queues are used to seed the db with the trades/orders happen by the matching engine.
-        let ltp_key_name = format!("orderbook:ltp:{}", symbol_key_part);
-        let snapshot_key_name = format!("orderbook:snapshot:{}", symbol_key_part);
-        let delta_channel_name = format!("orderbook:deltas:{}", symbol_key_part);
-        let bids_orderbook_key = format!("orderbook:bids:{}", symbol_key_part);
-        let asks_orderbook_key = format!("orderbook:asks:{}", symbol_key_part);
-        println!("Initializing consumer for symbol '{}'...", config.symbol);
-        println!(" -> Orders Queue:       {}", order_queue_name);
-        println!(" -> Cancel Queue:       {}", cancel_queue_name);
-        println!(" -> Trades Stream:      {}", trade_queue_name);
-        println!(" -> Snapshot Requests:  {}:requests", snapshot_key_name);
-        println!(" -> Deltas Channel:     {}", delta_channel_name);
-        println!(" -> Redis Bids:         {}", bids_orderbook_key);
-        println!(" -> Redis Asks:         {}", asks_orderbook_key);
-        println!(
-            " -> Processed Orders Queue:   {}",
-            processed_orders_queue_name
-        );
-        println!(
-            " -> Processed Trades Queue:   {}",
-            processed_trades_queue_name
-        );
-
-        let engine = Arc::new(Mutex::new(MatchingEngine::new(config.symbol.clone())));
-
-        let consumer = match OrderConsumer::new(
-            REDIS_URL,
-            engine,
-            config.symbol.clone(),
-            order_queue_name.clone(),
-            cancel_queue_name.clone(),
-            trade_queue_name.clone(),
-            processed_trades_queue_name.clone(),
-            processed_orders_queue_name.clone(),
-            ltp_key_name.clone(),
-            snapshot_key_name.clone(),
-            delta_channel_name.clone(),
-            bids_orderbook_key.clone(),
-            asks_orderbook_key.clone(),
-        )
-        .await
-        {
-            Ok(c) => c,
+    // Offline runs process one market and end once the input is exhausted and every output
+    // has been written.
+    if let Some(args) = &options.offline {
+        let pair = trading_pair_configs
+            .iter()
+            .find(|pair| pair.enabled && args.market.as_ref().is_none_or(|m| *m == pair.symbol));
+        let Some(pair) = pair else {
+            info!("No enabled trading pairs found or no consumers could be started. Exiting.");
+            return ExitCode::SUCCESS;
+        };
+        let consumer = match open_market(&config, pair, MarketIo::Offline(args)).await {
+            Ok(consumer) => consumer,
             Err(e) => {
-                eprintln!("Failed to create consumer for {}: {}", config.symbol, e);
-                continue;
+                error!("Failed to create consumer for {}: {}", pair.symbol, e);
+                return ExitCode::FAILURE;
             }
         };
-
-        // Initialize Redis orderbook with current state
-        consumer.initialize_redis_orderbook().await;
-
-        let handle = tokio::spawn(async move {
-            consumer.run_consumer().await;
-        });
-        consumer_handles.push(handle);
+        let run = consumer.run_consumer();
+        tokio::pin!(run);
+        let checkpointed = tokio::select! {
+            checkpointed = &mut run => checkpointed.is_ok(),
+            signal = signals.recv() => {
+                info!("\n{} received, shutting down.", signal);
+                consumer.stop();
+                drain(&mut signals, async { run.await.is_ok() }).await
+            }
+        };
+        info!("Application shut down.");
+        return exit_code(checkpointed);
     }
 
-    if consumer_handles.is_empty() {
-        println!("No enabled trading pairs found or no consumers could be started. Exiting.");
-        return;
+    let mut markets = Markets::new(config.clone());
+    markets.reconcile(trading_pair_configs).await;
+    if markets.is_empty() && config.markets_watch_interval == 0 {
+        info!("No enabled trading pairs found or no consumers could be started. Exiting.");
+        return ExitCode::SUCCESS;
     }
 
-    println!("\nAll enabled matching engines are running.");
-    println!("\n--- Example Commands for BTC_INR ---");
+    info!("\nAll enabled matching engines are running.");
+    let example = RedisKeys::new(&config.key_prefix, "BTC_INR");
+    info!("\n--- Example Commands for BTC_INR ---");
+
+    info!("\n1. Submit Orders:");
+    info!(
+        r#"LPUSH {} '{{"command":"NewOrder","payload":{{"user_id":1,"order_type":"Limit","side":"Buy","price":"50000","quantity":"1.5"}}}}'"#,
+        example.orders
+    );
+    info!(
+        r#"LPUSH {} '{{"command":"NewOrder","payload":{{"user_id":2,"order_type":"Market","side":"Sell","price":"0","quantity":"0.5"}}}}'"#,
+        example.orders
+    );
 
-    println!("\n1. Submit Orders:");
-    println!(
-        r#"LPUSH orderbook:orders:BTC_INR '{{"command":"NewOrder","payload":{{"user_id":1,"order_type":"Limit","side":"Buy","price":"50000","quantity":"1.5"}}}}'"#
+    info!("\n2. Cancel Orders:");
+    info!(
+        r#"LPUSH {} '{{"command":"CancelOrder","payload":{{"order_id":"xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx"}}}}'"#,
+        example.cancels
     );
-    println!(
-        r#"LPUSH orderbook:orders:BTC_INR '{{"command":"NewOrder","payload":{{"user_id":2,"order_type":"Market","side":"Sell","price":"0","quantity":"0.5"}}}}'"#
+
+    info!("\n3. Modify Orders (price and/or remaining quantity):");
+    info!(
+        r#"LPUSH {} '{{"command":"ModifyOrder","payload":{{"order_id":"xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx","new_price":"50100","new_quantity":"1.0"}}}}'"#,
+        example.cancels
     );
 
-    println!("\n2. Cancel Orders:");
-    println!(
-        r#"LPUSH orderbook:cancel:BTC_INR '{{"command":"CancelOrder","payload":{{"order_id":"xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx"}}}}'"#
+    info!("\n4. Request Snapshot:");
+    info!(
+        r#"LPUSH {} '{{"command":"SnapshotRequest","payload":{{"response_channel":"my_snapshot_channel"}}}}'"#,
+        example.snapshot_requests
     );
+    info!("SUBSCRIBE my_snapshot_channel");
 
-    println!("\n3. Request Snapshot:");
-    println!(
-        r#"LPUSH orderbook:snapshot:BTC_INR:requests '{{"command":"SnapshotRequest","payload":{{"response_channel":"my_snapshot_channel"}}}}'"#
+    info!("\n5. Halt, Restrict or Resume Trading (Trading, PostOnly, CancelOnly, Halted):");
+    info!(
+        r#"LPUSH {} '{{"command":"SetMarketStatus","payload":{{"status":"Halted"}}}}'"#,
+        example.admin
     );
-    println!("SUBSCRIBE my_snapshot_channel");
+    info!("GET {}", example.status);
+
+    info!("\n6. Query Redis Orderbook Directly:");
+    info!("# Top 10 bid prices (highest first), then their quantities:");
+    info!("ZREVRANGE {} 0 9", example.bids);
+    info!("HMGET {} <price> ...", example.bid_levels);
+    info!("# Top 10 ask prices (lowest first):");
+    info!("ZRANGE {} 0 9", example.asks);
+
+    info!("\n7. Real-time Updates:");
+    info!("SUBSCRIBE {}", example.deltas_channel);
+    info!("SUBSCRIBE {}", example.events_channel);
 
-    println!("\n4. Query Redis Orderbook Directly:");
-    println!("# Top 10 bids (highest first):");
-    println!("ZREVRANGE orderbook:bids:BTC_INR 0 9 WITHSCORES");
-    println!("# Top 10 asks (lowest first):");
-    println!("ZRANGE orderbook:asks:BTC_INR 0 9 WITHSCORES");
+    info!("\n8. Trade History:");
+    info!("LRANGE {} 0 10", example.trades_channel);
 
-    println!("\n5. Real-time Updates:");
-    println!("SUBSCRIBE orderbook:deltas:BTC_INR");
+    info!("\n9. Last Traded Price:");
+    info!("GET {}", example.ltp);
 
-    println!("\n6. Trade History:");
-    println!("LRANGE orderbook:trades:BTC_INR 0 10");
+    let watch = async {
+        match config.markets_watch_interval {
+            0 => markets.watch(EXITED_CHECK_INTERVAL, false).await,
+            seconds => markets.watch(Duration::from_secs(seconds), true).await,
+        }
+    };
+    tokio::select! {
+        _ = watch => {}
+        signal = signals.recv() => info!("\n{} received, shutting down.", signal),
+    }
 
-    println!("\n7. Last Traded Price:");
-    println!("GET orderbook:ltp:BTC_INR");
+    // Every market stops reading, publishes what it has already read and snapshots its
+    // book before the process exits.
+    let clean = drain(&mut signals, markets.stop_all()).await;
+    info!("Application shut down.");
+    exit_code(clean)
+}
 
-    match tokio::signal::ctrl_c().await {
-        Ok(()) => println!("\nCtrl+C received, shutting down."),
-        Err(err) => eprintln!("Failed to listen for Ctrl+C: {}", err),
+/// Waits for the markets to finish `draining` after a shutdown signal. A second signal
+/// exits at once; whatever was not snapshotted is then replayed from the journal on the
+/// next start.
+async fn drain(signals: &mut ShutdownSignals, draining: impl Future<Output = bool>) -> bool {
+    tokio::select! {
+        clean = draining => clean,
+        signal = signals.recv() => {
+            warn!("{} received again, exiting without waiting for the markets to drain.", signal);
+            std::process::exit(130);
+        }
     }
+}
 
-    println!("Application shut down.");
+/// 0 once every market drained and wrote its final snapshot, 1 otherwise.
+fn exit_code(clean: bool) -> ExitCode {
+    if clean {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
@@ -0,0 +1,492 @@
+// markets.rs
+use crate::config::{Config, MarketOverrides, OfflineArgs};
+use log::{error, info, warn};
+use rust_matching_engine::consumer::OrderConsumer;
+use rust_matching_engine::journal::{self, Journal};
+use rust_matching_engine::redis_transport::{RedisInput, RedisKeys, RedisSink};
+use rust_matching_engine::snapshot::SnapshotStore;
+use rust_matching_engine::transport::{InputSource, JsonLinesSink, LineInput, OutputSink};
+use rust_matching_engine::{FeeSchedule, MarketRules, MatchingEngine, SelfTradePrevention};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+const SNAPSHOTS_TO_KEEP: usize = 3;
+/// How long a market waits before its second start in a row that follows a failure; each
+/// further one waits twice as long. The first restart is immediate.
+const RESTART_BACKOFF: Duration = Duration::from_secs(5);
+/// Failures in a row after which a market is left stopped until the markets file is
+/// reloaded.
+const MAX_FAILURES: u32 = 5;
+/// A consumer that ran this long before exiting starts its count of failures over.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TradingPairConfig {
+    pub symbol: String,
+    base_asset: String,
+    quote_asset: String,
+    pub enabled: bool,
+    #[allow(dead_code)]
+    description: String,
+    #[serde(default)]
+    self_trade_prevention: Option<SelfTradePrevention>,
+    #[serde(default)]
+    fees: FeeSchedule,
+    /// Client order ids remembered per user; defaults to `DEFAULT_DEDUP_WINDOW`.
+    #[serde(default)]
+    dedup_window: Option<usize>,
+    /// Commands between snapshots; defaults to the config's `snapshot_interval`.
+    #[serde(default)]
+    snapshot_interval: Option<u64>,
+    #[serde(flatten)]
+    rules: MarketRules,
+}
+
+impl TradingPairConfig {
+    /// The `BASE_QUOTE` part of the market's Redis keys, journal and snapshot names.
+    fn key_part(&self) -> String {
+        format!(
+            "{}_{}",
+            self.base_asset.to_uppercase(),
+            self.quote_asset.to_uppercase()
+        )
+    }
+
+    fn apply(&mut self, overrides: &MarketOverrides) {
+        self.enabled = overrides.enabled.unwrap_or(self.enabled);
+        if overrides.self_trade_prevention.is_some() {
+            self.self_trade_prevention = overrides.self_trade_prevention;
+        }
+        if overrides.dedup_window.is_some() {
+            self.dedup_window = overrides.dedup_window;
+        }
+        if overrides.snapshot_interval.is_some() {
+            self.snapshot_interval = overrides.snapshot_interval;
+        }
+    }
+}
+
+/// Reads the markets file and applies the config's per-market overrides to it. A market
+/// whose rules cannot be enforced is disabled.
+pub fn load_markets(config: &Config) -> Result<Vec<TradingPairConfig>, String> {
+    let path = config.markets_file.display();
+    let content = fs::read_to_string(&config.markets_file)
+        .map_err(|e| format!("Failed to read markets file '{}': {}", path, e))?;
+    let mut pairs: Vec<TradingPairConfig> = serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse markets file '{}': {}", path, e))?;
+    for pair in &mut pairs {
+        pair.apply(&config.market(&pair.symbol));
+        if pair.enabled
+            && let Err(e) = pair.rules.validate()
+        {
+            error!("Refusing to start market '{}': {}", pair.symbol, e);
+            pair.enabled = false;
+        }
+    }
+    Ok(pairs)
+}
+
+async fn open_offline_io(
+    args: &OfflineArgs,
+) -> std::io::Result<(Box<dyn InputSource>, Box<dyn OutputSink>)> {
+    let input: Box<dyn InputSource> = match args.input.as_str() {
+        "-" => Box::new(LineInput::stdin()),
+        path => Box::new(LineInput::open(Path::new(path)).await?),
+    };
+    let output: Box<dyn OutputSink> = match args.output.as_str() {
+        "-" => Box::new(JsonLinesSink::stdout()),
+        path => Box::new(JsonLinesSink::create(Path::new(path)).await?),
+    };
+    Ok((input, output))
+}
+
+async fn connect_redis(
+    config: &Config,
+    keys: &RedisKeys,
+) -> redis::RedisResult<(Box<dyn InputSource>, Box<dyn OutputSink>)> {
+    let url = config.redis_url.as_str();
+    let input = RedisInput::connect(url, config.transport, keys.clone()).await?;
+    let output =
+        RedisSink::connect(url, config.transport, config.delta_format, keys.clone()).await?;
+    Ok((Box::new(input), Box::new(output)))
+}
+
+/// Opens the input and output of the market with the given symbol.
+pub type Connect = dyn Fn(&str) -> (Box<dyn InputSource>, Box<dyn OutputSink>) + Send + Sync;
+
+/// Where a market's commands come from and its outputs go.
+pub enum MarketIo<'a> {
+    Redis,
+    Offline(&'a OfflineArgs),
+    /// Whatever the caller connects, e.g. in-process channels.
+    Custom(&'a Connect),
+}
+
+/// Builds the consumer of one market: restores its book from the latest snapshot and the
+/// journal, then connects it to Redis, to the offline input and output, or to `io`'s own.
+pub async fn open_market(
+    config: &Config,
+    pair: &TradingPairConfig,
+    io: MarketIo<'_>,
+) -> Result<OrderConsumer, String> {
+    let symbol_key_part = pair.key_part();
+    let keys = RedisKeys::new(&config.key_prefix, &symbol_key_part);
+    info!("Initializing consumer for symbol '{}'...", pair.symbol);
+    if let MarketIo::Redis = io {
+        info!(" -> Orders Queue:       {}", keys.orders);
+        info!(" -> Cancel Queue:       {}", keys.cancels);
+        info!(" -> Trades Stream:      {}", keys.trades_channel);
+        info!(" -> Snapshot Requests:  {}", keys.snapshot_requests);
+        info!(" -> Admin Queue:        {}", keys.admin);
+        info!(" -> Market Status:      {}", keys.status);
+        info!(" -> Deltas Channel:     {}", keys.deltas_channel);
+        info!(" -> Events Channel:     {}", keys.events_channel);
+        info!(" -> Redis Bids:         {}", keys.bids);
+        info!(" -> Redis Asks:         {}", keys.asks);
+        info!(" -> Processed Orders Queue:   {}", keys.processed_orders);
+        info!(" -> Processed Trades Queue:   {}", keys.processed_trades);
+        info!(" -> Rejections Queue:         {}", keys.rejections);
+    }
+
+    let mut engine = MatchingEngine::new(pair.symbol.clone());
+    engine.set_self_trade_prevention(pair.self_trade_prevention);
+    engine.set_market_rules(pair.rules.clone());
+    engine.set_assets(pair.base_asset.clone(), pair.quote_asset.clone());
+    engine.set_fee_schedule(pair.fees.clone());
+    if let Some(window) = pair.dedup_window {
+        engine.set_dedup_window(window);
+    }
+
+    // Rebuild the book from the latest snapshot and the journal entries recorded after it,
+    // before anything is published to Redis.
+    let snapshots = SnapshotStore::new(&config.snapshot_dir, &symbol_key_part, SNAPSHOTS_TO_KEEP);
+    let (snapshot_sequence, stream_positions) = match snapshots.load_latest() {
+        Ok(Some(snapshot)) if snapshot.state.symbol() != pair.symbol => {
+            return Err(format!(
+                "snapshot belongs to market '{}'",
+                snapshot.state.symbol()
+            ));
+        }
+        Ok(Some(snapshot)) => {
+            info!(
+                " -> Snapshot:           journal sequence {} (taken {})",
+                snapshot.journal_sequence, snapshot.taken_at
+            );
+            engine.restore_state(snapshot.state);
+            (snapshot.journal_sequence, snapshot.stream_positions)
+        }
+        Ok(None) => (0, BTreeMap::new()),
+        Err(e) => return Err(format!("failed to read snapshots: {}", e)),
+    };
+    let journal_path = config
+        .journal_dir
+        .join(format!("{}.journal", symbol_key_part));
+    let journal = match Journal::open(&journal_path, config.journal_fsync, snapshot_sequence) {
+        Ok((mut journal, entries)) => {
+            journal.merge_stream_positions(&stream_positions);
+            for (sequence, output) in journal::replay(&mut engine, &entries) {
+                journal
+                    .record_output(sequence, &output)
+                    .map_err(|e| format!("failed to record replayed output: {}", e))?;
+            }
+            info!(
+                " -> Journal:            {} ({} commands replayed, sequence {})",
+                journal.path().display(),
+                entries.len(),
+                engine.sequence()
+            );
+            journal
+        }
+        Err(e) => {
+            return Err(format!(
+                "failed to open journal '{}': {}",
+                journal_path.display(),
+                e
+            ));
+        }
+    };
+
+    let (input, output) = match io {
+        MarketIo::Offline(args) => open_offline_io(args)
+            .await
+            .map_err(|e| format!("failed to open offline input/output: {}", e))?,
+        MarketIo::Redis => connect_redis(config, &keys)
+            .await
+            .map_err(|e| format!("failed to connect to Redis: {}", e))?,
+        MarketIo::Custom(connect) => connect(&pair.symbol),
+    };
+    Ok(OrderConsumer::new(
+        Arc::new(Mutex::new(engine)),
+        input,
+        output,
+        journal,
+        snapshots,
+        pair.snapshot_interval.unwrap_or(config.snapshot_interval),
+        pair.symbol.clone(),
+    ))
+}
+
+struct RunningMarket {
+    pair: TradingPairConfig,
+    consumer: Arc<OrderConsumer>,
+    handle: JoinHandle<std::io::Result<()>>,
+    started_at: Instant,
+    // Failures in a row before this start.
+    failures: u32,
+}
+
+/// A market that failed to start or whose consumer exited, waiting to be started again.
+struct FailedMarket {
+    pair: TradingPairConfig,
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// The markets this process runs, kept in line with the markets file while it runs.
+pub struct Markets {
+    config: Config,
+    running: BTreeMap<String, RunningMarket>,
+    failed: BTreeMap<String, FailedMarket>,
+    restart_backoff: Duration,
+    // Connects the markets instead of Redis when set.
+    connect: Option<Arc<Connect>>,
+}
+
+impl Markets {
+    pub fn new(config: Config) -> Self {
+        Markets {
+            config,
+            running: BTreeMap::new(),
+            failed: BTreeMap::new(),
+            restart_backoff: RESTART_BACKOFF,
+            connect: None,
+        }
+    }
+
+    /// Markets whose inputs and outputs come from `connect` instead of Redis, restarted
+    /// after `restart_backoff` instead of `RESTART_BACKOFF`.
+    #[cfg(test)]
+    pub fn with_io(config: Config, connect: Arc<Connect>, restart_backoff: Duration) -> Self {
+        Markets {
+            connect: Some(connect),
+            restart_backoff,
+            ..Markets::new(config)
+        }
+    }
+
+    /// True if no market is running or waiting to be started again.
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty() && self.failed.is_empty()
+    }
+
+    /// Starts every enabled market of `pairs` that is not running, and stops every running
+    /// market that is no longer in `pairs` or no longer enabled. A market whose settings
+    /// changed, or whose consumer exited on its own, is stopped and started again with the
+    /// new settings. Markets that are left alone keep running undisturbed. Markets that
+    /// failed are started right away, with their count of failures reset.
+    pub async fn reconcile(&mut self, pairs: Vec<TradingPairConfig>) {
+        self.failed.clear();
+        // A market that was disabled, removed or changed no longer matches any pair.
+        let stale: Vec<String> = self
+            .running
+            .values()
+            .filter(|market| !pairs.contains(&market.pair) || market.handle.is_finished())
+            .map(|market| market.pair.symbol.clone())
+            .collect();
+        for symbol in stale {
+            self.stop_market(&symbol).await;
+        }
+
+        for pair in pairs {
+            if !pair.enabled {
+                if !self.running.contains_key(&pair.symbol) {
+                    info!("Skipping disabled trading pair: {}", pair.symbol);
+                }
+                continue;
+            }
+            if !self.running.contains_key(&pair.symbol) {
+                self.start_market(pair, 0).await;
+            }
+        }
+    }
+
+    /// Starts again, with the settings it had, every market whose consumer exited on its
+    /// own (e.g. because it panicked) or that failed to start. The first restart is
+    /// immediate; a market that keeps failing waits longer each time and is given up after
+    /// `MAX_FAILURES` in a row.
+    pub async fn restart_exited(&mut self) {
+        let exited: Vec<String> = self
+            .running
+            .values()
+            .filter(|market| market.handle.is_finished())
+            .map(|market| market.pair.symbol.clone())
+            .collect();
+        for symbol in exited {
+            warn!("[{}] Consumer exited", symbol);
+            let Some(market) = self.stop_market(&symbol).await else {
+                continue;
+            };
+            let failures = if market.started_at.elapsed() >= STABLE_AFTER {
+                1
+            } else {
+                market.failures + 1
+            };
+            self.record_failure(market.pair, failures);
+        }
+
+        let now = Instant::now();
+        let due: Vec<String> = self
+            .failed
+            .values()
+            .filter(|market| market.retry_at <= now)
+            .map(|market| market.pair.symbol.clone())
+            .collect();
+        for symbol in due {
+            if let Some(market) = self.failed.remove(&symbol) {
+                info!(
+                    "[{}] Restarting after {} failure(s) in a row",
+                    symbol, market.failures
+                );
+                self.start_market(market.pair, market.failures).await;
+            }
+        }
+    }
+
+    /// Schedules the market's next start, or gives up on it after `MAX_FAILURES`.
+    fn record_failure(&mut self, pair: TradingPairConfig, failures: u32) {
+        if failures >= MAX_FAILURES {
+            error!(
+                "[{}] Failed {} times in a row; leaving it stopped until the markets file is reloaded",
+                pair.symbol, failures
+            );
+            return;
+        }
+        let delay = match failures {
+            0 | 1 => Duration::ZERO,
+            n => self.restart_backoff * 2u32.pow(n - 2),
+        };
+        self.failed.insert(
+            pair.symbol.clone(),
+            FailedMarket {
+                pair,
+                failures,
+                retry_at: Instant::now() + delay,
+            },
+        );
+    }
+
+    /// Opens the market in a task of its own, so that a panic while its book is rebuilt
+    /// fails this market alone, then runs its consumer.
+    async fn start_market(&mut self, pair: TradingPairConfig, failures: u32) {
+        let opened = tokio::spawn({
+            let config = self.config.clone();
+            let pair = pair.clone();
+            let connect = self.connect.clone();
+            async move {
+                let io = match &connect {
+                    Some(connect) => MarketIo::Custom(connect.as_ref()),
+                    None => MarketIo::Redis,
+                };
+                open_market(&config, &pair, io).await
+            }
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+        let consumer = match opened {
+            Ok(consumer) => Arc::new(consumer),
+            Err(e) => {
+                error!("Failed to create consumer for {}: {}", pair.symbol, e);
+                self.record_failure(pair, failures + 1);
+                return;
+            }
+        };
+        let handle = tokio::spawn({
+            let consumer = consumer.clone();
+            async move { consumer.run_consumer().await }
+        });
+        self.running.insert(
+            pair.symbol.clone(),
+            RunningMarket {
+                pair,
+                consumer,
+                handle,
+                started_at: Instant::now(),
+                failures,
+            },
+        );
+    }
+
+    /// Drains the market's consumer, waits until its book has been snapshotted and returns
+    /// what it was running.
+    async fn stop_market(&mut self, symbol: &str) -> Option<RunningMarket> {
+        // Stays listed until its consumer has returned, so that a shutdown interrupting
+        // this still waits for it.
+        let market = self.running.get_mut(symbol)?;
+        info!("[{}] Stopping market...", symbol);
+        market.consumer.stop();
+        finish(symbol, &mut market.handle).await;
+        self.running.remove(symbol)
+    }
+
+    /// Stops every market at once: each stops reading, publishes what it has already
+    /// read and writes a final snapshot. Returns whether all of them did so cleanly.
+    pub async fn stop_all(&mut self) -> bool {
+        let markets = std::mem::take(&mut self.running);
+        info!("Stopping {} market(s)...", markets.len());
+        for market in markets.values() {
+            market.consumer.stop();
+        }
+        let mut clean = true;
+        for (symbol, mut market) in markets {
+            clean &= finish(&symbol, &mut market.handle).await;
+        }
+        clean
+    }
+
+    /// Every `interval`, restarts the markets whose consumer exited and, with `reload`,
+    /// re-reads the markets file if it changed and reconciles the running markets with
+    /// it. Never returns.
+    pub async fn watch(&mut self, interval: Duration, reload: bool) {
+        let mut last_modified = modified(&self.config.markets_file);
+        loop {
+            tokio::time::sleep(interval).await;
+            let modified = modified(&self.config.markets_file);
+            if !reload || modified == last_modified {
+                self.restart_exited().await;
+                continue;
+            }
+            last_modified = modified;
+            info!(
+                "Markets file '{}' changed, reloading",
+                self.config.markets_file.display()
+            );
+            match load_markets(&self.config) {
+                Ok(pairs) => self.reconcile(pairs).await,
+                Err(e) => error!("{}; keeping the running markets", e),
+            }
+        }
+    }
+}
+
+/// Waits for a stopped market's consumer to return, and reports whether it checkpointed.
+async fn finish(symbol: &str, handle: &mut JoinHandle<std::io::Result<()>>) -> bool {
+    match handle.await {
+        Ok(Ok(())) => true,
+        // The consumer has already logged why its final checkpoint failed.
+        Ok(Err(_)) => false,
+        Err(e) => {
+            error!("[{}] Consumer task failed: {}", symbol, e);
+            false
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
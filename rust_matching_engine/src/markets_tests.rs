@@ -0,0 +1,321 @@
+use crate::config::Config;
+use crate::markets::*;
+use rust_matching_engine::snapshot::SnapshotStore;
+use rust_matching_engine::transport::*;
+use rust_matching_engine::{EngineEvent, OrderBookSnapshot};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// The channels of one start of a market, until a test takes them.
+#[derive(Default)]
+struct Started {
+    commands: Option<mpsc::Sender<String>>,
+    outputs: Option<mpsc::UnboundedReceiver<Output>>,
+}
+
+/// Every start of every market so far, by symbol, oldest first.
+type Opened = Arc<Mutex<BTreeMap<String, Vec<Started>>>>;
+
+fn temp_data_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("markets-test-{}", Uuid::new_v4()))
+}
+
+fn data_config(dir: &Path) -> Config {
+    Config {
+        journal_dir: dir.join("journal"),
+        snapshot_dir: dir.join("snapshots"),
+        ..Config::default()
+    }
+}
+
+/// Connects every market to a fresh pair of channels, listed in `opened`.
+fn connect_channels(
+    opened: Opened,
+) -> impl Fn(&str) -> (Box<dyn InputSource>, Box<dyn OutputSink>) + Send + Sync {
+    move |symbol| {
+        let (commands, input) = channel_input(16);
+        let (sink, outputs) = channel_sink();
+        opened
+            .lock()
+            .unwrap()
+            .entry(symbol.to_string())
+            .or_default()
+            .push(Started {
+                commands: Some(commands),
+                outputs: Some(outputs),
+            });
+        (Box::new(input), Box::new(sink))
+    }
+}
+
+/// Markets that keep their journals and snapshots under `dir` and run over channels.
+fn markets(dir: &Path) -> (Markets, Opened) {
+    let opened = Opened::default();
+    let connect = connect_channels(opened.clone());
+    let markets = Markets::with_io(data_config(dir), Arc::new(connect), Duration::ZERO);
+    (markets, opened)
+}
+
+fn pair(symbol: &str, enabled: bool, snapshot_interval: u64) -> TradingPairConfig {
+    let (base, quote) = symbol.split_once('/').unwrap();
+    serde_json::from_value(serde_json::json!({
+        "symbol": symbol,
+        "base_asset": base,
+        "quote_asset": quote,
+        "enabled": enabled,
+        "description": symbol,
+        "snapshot_interval": snapshot_interval,
+    }))
+    .unwrap()
+}
+
+fn starts(opened: &Opened, symbol: &str) -> usize {
+    opened.lock().unwrap().get(symbol).map_or(0, Vec::len)
+}
+
+/// The channels of the market's latest start.
+fn take(opened: &Opened, symbol: &str) -> (mpsc::Sender<String>, mpsc::UnboundedReceiver<Output>) {
+    let mut opened = opened.lock().unwrap();
+    let started = opened.get_mut(symbol).unwrap().last_mut().unwrap();
+    (
+        started.commands.take().unwrap(),
+        started.outputs.take().unwrap(),
+    )
+}
+
+fn new_order_json(side: &str, price: &str, quantity: &str) -> String {
+    format!(
+        r#"{{"command":"NewOrder","payload":{{"id":"{}","user_id":"{}","order_type":"Limit","side":"{}","price":"{}","quantity":"{}","timestamp":"2024-01-01T00:00:00Z"}}}}"#,
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        side,
+        price,
+        quantity
+    )
+}
+
+/// Sends one order and returns the output published for it.
+async fn submit(
+    commands: &mpsc::Sender<String>,
+    outputs: &mut mpsc::UnboundedReceiver<Output>,
+    order_json: String,
+) -> CommandOutput {
+    commands.send(order_json).await.unwrap();
+    match tokio::time::timeout(Duration::from_secs(5), outputs.recv()).await {
+        Ok(Some(Output::Command(output))) => output,
+        other => panic!("expected the order's output, got {:?}", other),
+    }
+}
+
+fn latest_snapshot(dir: &Path, key_part: &str) -> (u64, OrderBookSnapshot) {
+    let snapshot = SnapshotStore::new(&dir.join("snapshots"), key_part, 1)
+        .load_latest()
+        .unwrap()
+        .expect("the market was snapshotted");
+    let mut engine = rust_matching_engine::MatchingEngine::new(snapshot.state.symbol().into());
+    engine.restore_state(snapshot.state);
+    (snapshot.journal_sequence, engine.get_order_book_snapshot())
+}
+
+#[tokio::test]
+async fn test_enabled_markets_start_and_disabled_ones_drain() {
+    let dir = temp_data_dir();
+    let (mut markets, opened) = markets(&dir);
+    markets
+        .reconcile(vec![
+            pair("BTC/INR", true, 100),
+            pair("ETH/USD", false, 100),
+        ])
+        .await;
+    assert_eq!(starts(&opened, "BTC/INR"), 1);
+    assert_eq!(starts(&opened, "ETH/USD"), 0);
+
+    let (btc, mut btc_outputs) = take(&opened, "BTC/INR");
+    let output = submit(&btc, &mut btc_outputs, new_order_json("Sell", "100", "5")).await;
+    assert_eq!(output.command_id, 1);
+
+    // Disabling BTC drains it and snapshots its book; enabling ETH starts it.
+    markets
+        .reconcile(vec![
+            pair("BTC/INR", false, 100),
+            pair("ETH/USD", true, 100),
+        ])
+        .await;
+    assert_eq!(starts(&opened, "BTC/INR"), 1);
+    assert_eq!(starts(&opened, "ETH/USD"), 1);
+    let (sequence, book) = latest_snapshot(&dir, "BTC_INR");
+    assert_eq!(sequence, 1);
+    assert_eq!(book.asks[0].quantity, rust_decimal_macros::dec!(5));
+
+    assert!(markets.stop_all().await);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_changed_market_restarts_and_the_others_keep_running() {
+    let dir = temp_data_dir();
+    let (mut markets, opened) = markets(&dir);
+    markets
+        .reconcile(vec![pair("BTC/INR", true, 100), pair("ETH/USD", true, 100)])
+        .await;
+    let (btc, mut btc_outputs) = take(&opened, "BTC/INR");
+    let (eth, mut eth_outputs) = take(&opened, "ETH/USD");
+    submit(&btc, &mut btc_outputs, new_order_json("Sell", "100", "5")).await;
+    submit(&eth, &mut eth_outputs, new_order_json("Sell", "10", "5")).await;
+
+    markets
+        .reconcile(vec![pair("BTC/INR", true, 50), pair("ETH/USD", true, 100)])
+        .await;
+    assert_eq!(starts(&opened, "BTC/INR"), 2);
+    assert_eq!(starts(&opened, "ETH/USD"), 1);
+
+    // The old BTC consumer drained and snapshotted; the new one carries on from its book.
+    assert_eq!(latest_snapshot(&dir, "BTC_INR").0, 1);
+    let (btc, mut btc_outputs) = take(&opened, "BTC/INR");
+    let output = submit(&btc, &mut btc_outputs, new_order_json("Buy", "100", "2")).await;
+    assert_eq!(output.command_id, 2);
+    assert_eq!(output.trades[0].quantity, rust_decimal_macros::dec!(2));
+
+    // ETH was not touched: the same consumer is still reading from the same channel.
+    let output = submit(&eth, &mut eth_outputs, new_order_json("Buy", "10", "1")).await;
+    assert_eq!(output.command_id, 2);
+    assert_eq!(output.trades.len(), 1);
+    assert!(markets.stop_all().await);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_exited_consumer_is_restarted() {
+    let dir = temp_data_dir();
+    let (mut markets, opened) = markets(&dir);
+    markets.reconcile(vec![pair("BTC/INR", true, 100)]).await;
+    let (btc, mut btc_outputs) = take(&opened, "BTC/INR");
+    submit(&btc, &mut btc_outputs, new_order_json("Sell", "100", "5")).await;
+
+    // An exhausted input ends the consumer without anyone stopping it.
+    drop(btc);
+    for _ in 0..500 {
+        markets.restart_exited().await;
+        if starts(&opened, "BTC/INR") == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(starts(&opened, "BTC/INR"), 2);
+
+    let (btc, mut btc_outputs) = take(&opened, "BTC/INR");
+    let output = submit(&btc, &mut btc_outputs, new_order_json("Buy", "100", "5")).await;
+    assert_eq!(output.command_id, 2);
+    assert!(
+        output
+            .events
+            .iter()
+            .any(|event| matches!(event, EngineEvent::ExecutionReport(_)))
+    );
+    assert_eq!(output.trades.len(), 1);
+    assert!(markets.stop_all().await);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_market_that_panics_on_start_is_retried_then_given_up() {
+    let dir = temp_data_dir();
+    let opened = Opened::default();
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let connect = {
+        let connect_channels = connect_channels(opened.clone());
+        let attempts = attempts.clone();
+        move |symbol: &str| {
+            if symbol == "BAD/INR" {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                panic!("rebuilding the book failed");
+            }
+            connect_channels(symbol)
+        }
+    };
+    let mut markets = Markets::with_io(data_config(&dir), Arc::new(connect), Duration::ZERO);
+
+    // The panic fails BAD alone; BTC starts and keeps running.
+    markets
+        .reconcile(vec![pair("BAD/INR", true, 100), pair("BTC/INR", true, 100)])
+        .await;
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    assert_eq!(starts(&opened, "BTC/INR"), 1);
+    for _ in 0..10 {
+        markets.restart_exited().await;
+    }
+    assert_eq!(attempts.load(Ordering::SeqCst), 5);
+
+    let (btc, mut btc_outputs) = take(&opened, "BTC/INR");
+    let output = submit(&btc, &mut btc_outputs, new_order_json("Sell", "100", "5")).await;
+    assert_eq!(output.command_id, 1);
+    assert_eq!(starts(&opened, "BTC/INR"), 1);
+
+    // Reloading the markets file tries it again.
+    markets
+        .reconcile(vec![pair("BAD/INR", true, 100), pair("BTC/INR", true, 100)])
+        .await;
+    assert_eq!(attempts.load(Ordering::SeqCst), 6);
+    assert!(markets.stop_all().await);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_markets_with_unenforceable_rules_are_disabled() {
+    let dir = temp_data_dir();
+    fs::create_dir_all(&dir).unwrap();
+    let markets_file = dir.join("markets.json");
+    let market = |symbol: &str, rules: serde_json::Value| {
+        let mut market = serde_json::json!({
+            "symbol": symbol,
+            "base_asset": "BASE",
+            "quote_asset": "QUOTE",
+            "enabled": true,
+            "description": symbol,
+        });
+        market
+            .as_object_mut()
+            .unwrap()
+            .extend(rules.as_object().unwrap().clone());
+        market
+    };
+    let file = serde_json::json!([
+        market(
+            "OK",
+            serde_json::json!({"tick_size": "0.5", "lot_size": "0.01"})
+        ),
+        market("ZERO_TICK", serde_json::json!({"tick_size": "0"})),
+        market("ZERO_LOT", serde_json::json!({"lot_size": "0"})),
+        market(
+            "CROSSED",
+            serde_json::json!({"min_quantity": "10", "max_quantity": "1"})
+        ),
+    ]);
+    fs::write(&markets_file, file.to_string()).unwrap();
+    let config = Config {
+        markets_file,
+        ..Config::default()
+    };
+
+    let enabled: Vec<(String, bool)> = load_markets(&config)
+        .unwrap()
+        .into_iter()
+        .map(|pair| (pair.symbol, pair.enabled))
+        .collect();
+    assert_eq!(
+        enabled,
+        [
+            ("OK".to_string(), true),
+            ("ZERO_TICK".to_string(), false),
+            ("ZERO_LOT".to_string(), false),
+            ("CROSSED".to_string(), false),
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
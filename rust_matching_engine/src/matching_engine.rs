@@ -1,11 +1,13 @@
 // matching_engine.rs
 
 use chrono::{DateTime, Utc};
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
 use std::cmp::{Ordering, Reverse};
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
 // use cuid2::create_id
 use uuid::Uuid;
 
@@ -14,6 +16,10 @@ use uuid::Uuid;
 pub enum OrderType {
     Limit,
     Market,
+    /// Rests in the stop book until `stop_price` is reached, then becomes a `Limit` order.
+    StopLimit,
+    /// Rests in the stop book until `stop_price` is reached, then becomes a `Market` order.
+    StopMarket,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -22,6 +28,58 @@ pub enum OrderSide {
     Sell,
 }
 
+/// How long an order stays live once it reaches the book.
+/// Serialized as `GTC` / `IOC` / `FOK` to line up with the Prisma `TimeInForce` enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TimeInForce {
+    /// Good till canceled: any unfilled remainder rests in the book.
+    #[default]
+    Gtc,
+    /// Immediate or cancel: match what is possible, cancel the remainder.
+    Ioc,
+    /// Fill or kill: fill the whole quantity immediately or reject the order untouched.
+    Fok,
+}
+
+/// What a post-only order should do if it would cross the spread on arrival.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PostOnlyMode {
+    /// Reject the order outright.
+    Reject,
+    /// Re-price the order one tick behind the best opposite price and post it there.
+    Reprice,
+}
+
+/// What a market accepts. Snapshot requests are answered in every status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub enum MarketStatus {
+    /// Orders are accepted and matched.
+    #[default]
+    Trading,
+    /// Only limit orders that rest without taking are accepted: they are handled as
+    /// post-only, rejecting unless they ask to be repriced. Amendments that would cross
+    /// the spread are refused; cancels are allowed.
+    PostOnly,
+    /// Only cancels are accepted.
+    CancelOnly,
+    /// Nothing that changes the book is accepted, not even cancels.
+    Halted,
+}
+
+/// What to do when a taker would match against a resting order from the same user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SelfTradePrevention {
+    /// Cancel the remainder of the incoming (taker) order.
+    CancelNewest,
+    /// Cancel the resting (maker) order and keep matching the taker.
+    CancelOldest,
+    /// Cancel both the taker remainder and the resting order.
+    CancelBoth,
+    /// Reduce both orders by the overlapping quantity; whichever reaches zero is canceled.
+    DecrementAndCancel,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     #[serde(default = "Uuid::new_v4")]
@@ -35,6 +93,20 @@ pub struct Order {
     pub quantity: Decimal,
     #[serde(default = "Utc::now")]
     pub timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    /// Trigger price for `StopLimit` / `StopMarket` orders; ignored otherwise.
+    #[serde(default, with = "rust_decimal::serde::str_option")]
+    pub stop_price: Option<Decimal>,
+    /// Maker-only guarantee. `None` means the order may take liquidity as usual.
+    #[serde(default)]
+    pub post_only: Option<PostOnlyMode>,
+    /// Overrides the market's self-trade prevention mode for this order.
+    #[serde(default)]
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    /// Caller-assigned id used to recognise resubmissions of the same order by the same user.
+    #[serde(default)]
+    pub client_order_id: Option<String>,
 }
 
 impl Ord for Order {
@@ -57,42 +129,74 @@ impl Eq for Order {}
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     pub id: Uuid,
+    pub symbol: String,
     pub taker_order_id: Uuid,
     pub maker_order_id: Uuid,
+    /// Side of the incoming (aggressor) order.
+    pub taker_side: OrderSide,
+    pub buyer_user_id: Uuid,
+    pub seller_user_id: Uuid,
+    pub buy_order_id: Uuid,
+    pub sell_order_id: Uuid,
     #[serde(with = "rust_decimal::serde::str")]
     pub price: Decimal,
     #[serde(with = "rust_decimal::serde::str")]
     pub quantity: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub maker_fee: Decimal,
+    pub maker_fee_asset: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub taker_fee: Decimal,
+    pub taker_fee_asset: String,
     pub timestamp: DateTime<Utc>,
+    /// Position in the market's outbound sequence, shared with `OrderBookDelta`.
+    #[serde(default)]
+    pub sequence: u64,
 }
 
 impl Trade {
     pub fn new(
-        taker_order_id: Uuid,
-        maker_order_id: Uuid,
+        symbol: &str,
+        taker_order: &Order,
+        maker_order: &Order,
         price: Decimal,
         quantity: Decimal,
     ) -> Self {
+        let (buy_order, sell_order) = match taker_order.side {
+            OrderSide::Buy => (taker_order, maker_order),
+            OrderSide::Sell => (maker_order, taker_order),
+        };
         Self {
             id: Uuid::new_v4(),
-            taker_order_id,
-            maker_order_id,
+            symbol: symbol.to_string(),
+            taker_order_id: taker_order.id,
+            maker_order_id: maker_order.id,
+            taker_side: taker_order.side,
+            buyer_user_id: buy_order.user_id,
+            seller_user_id: sell_order.user_id,
+            buy_order_id: buy_order.id,
+            sell_order_id: sell_order.id,
             price,
             quantity,
+            maker_fee: Decimal::ZERO,
+            maker_fee_asset: String::new(),
+            taker_fee: Decimal::ZERO,
+            taker_fee_asset: String::new(),
             timestamp: Utc::now(),
+            sequence: 0,
         }
     }
 }
 
 // --- Structs for Data Publishing ---
-#[derive(Serialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum DeltaAction {
     New,
     Update,
     Delete,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OrderBookDelta {
     pub action: DeltaAction,
     pub side: OrderSide,
@@ -100,6 +204,10 @@ pub struct OrderBookDelta {
     pub price: Decimal,
     #[serde(with = "rust_decimal::serde::str")]
     pub new_quantity: Decimal,
+    /// Position in the market's outbound sequence, shared with `Trade`. Only a whole
+    /// `BookUpdate` carries both, so only its subscribers can treat a jump as a missed
+    /// update; per-delta messages skip the numbers that trades take on their own channel.
+    pub sequence: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -118,9 +226,538 @@ pub struct OrderBookSnapshot {
     #[serde(with = "rust_decimal::serde::str_option")]
     pub last_traded_price: Option<Decimal>,
     pub timestamp: DateTime<Utc>,
+    /// Sequence of the last trade or delta reflected in this snapshot. Updates at or below
+    /// it are already included and can be dropped.
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub status: MarketStatus,
+}
+
+/// Per-market trading constraints. Every field is optional; an unset field is not enforced.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MarketRules {
+    /// Prices (and stop prices) must be a multiple of this increment.
+    #[serde(default, with = "rust_decimal::serde::str_option")]
+    pub tick_size: Option<Decimal>,
+    /// Quantities must be a multiple of this increment.
+    #[serde(default, with = "rust_decimal::serde::str_option")]
+    pub lot_size: Option<Decimal>,
+    #[serde(default, with = "rust_decimal::serde::str_option")]
+    pub min_quantity: Option<Decimal>,
+    #[serde(default, with = "rust_decimal::serde::str_option")]
+    pub max_quantity: Option<Decimal>,
+    /// Minimum `price * quantity` for orders that carry a price (limit and stop-limit).
+    #[serde(default, with = "rust_decimal::serde::str_option")]
+    pub min_notional: Option<Decimal>,
+}
+
+impl MarketRules {
+    /// Checks that the rules can be enforced: increments must be positive and the quantity
+    /// bounds must not be crossed.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, increment) in [("tick_size", self.tick_size), ("lot_size", self.lot_size)] {
+            if let Some(increment) = increment
+                && increment <= dec!(0)
+            {
+                return Err(format!("{} {} must be positive", name, increment));
+            }
+        }
+        if let (Some(min_quantity), Some(max_quantity)) = (self.min_quantity, self.max_quantity)
+            && min_quantity > max_quantity
+        {
+            return Err(format!(
+                "min_quantity {} exceeds max_quantity {}",
+                min_quantity, max_quantity
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_price(&self, price: Decimal) -> Result<(), RejectReason> {
+        if price <= dec!(0) {
+            return Err(RejectReason::NonPositivePrice { price });
+        }
+        if let Some(tick_size) = self.tick_size
+            && !(price % tick_size).is_zero()
+        {
+            return Err(RejectReason::PriceNotOnTick { price, tick_size });
+        }
+        Ok(())
+    }
+
+    fn check_quantity(&self, quantity: Decimal) -> Result<(), RejectReason> {
+        if quantity <= dec!(0) {
+            return Err(RejectReason::NonPositiveQuantity { quantity });
+        }
+        if let Some(lot_size) = self.lot_size
+            && !(quantity % lot_size).is_zero()
+        {
+            return Err(RejectReason::QuantityNotOnLot { quantity, lot_size });
+        }
+        if let Some(min_quantity) = self.min_quantity
+            && quantity < min_quantity
+        {
+            return Err(RejectReason::QuantityBelowMinimum {
+                quantity,
+                min_quantity,
+            });
+        }
+        if let Some(max_quantity) = self.max_quantity
+            && quantity > max_quantity
+        {
+            return Err(RejectReason::QuantityAboveMaximum {
+                quantity,
+                max_quantity,
+            });
+        }
+        Ok(())
+    }
+
+    fn notional(price: Decimal, quantity: Decimal) -> Result<Decimal, RejectReason> {
+        price
+            .checked_mul(quantity)
+            .ok_or(RejectReason::NotionalOverflow { price, quantity })
+    }
+
+    fn check_notional(&self, price: Decimal, quantity: Decimal) -> Result<(), RejectReason> {
+        let notional = Self::notional(price, quantity)?;
+        if let Some(min_notional) = self.min_notional
+            && notional < min_notional
+        {
+            return Err(RejectReason::NotionalBelowMinimum {
+                notional,
+                min_notional,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Fee rates in basis points. A negative rate is a rebate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeeRates {
+    #[serde(default, with = "rust_decimal::serde::str")]
+    pub maker_fee_bps: Decimal,
+    #[serde(default, with = "rust_decimal::serde::str")]
+    pub taker_fee_bps: Decimal,
+}
+
+/// Per-market maker/taker fees, with optional per-user overrides.
+///
+/// Each side pays in the asset it receives: the buyer in the base asset on the traded
+/// quantity, the seller in the quote asset on `price * quantity`. The result is rounded to
+/// `fee_scale` decimal places towards positive infinity, so a charge is never rounded down
+/// and a rebate is never rounded up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    #[serde(flatten)]
+    pub rates: FeeRates,
+    #[serde(default = "FeeSchedule::default_fee_scale")]
+    pub fee_scale: u32,
+    /// Users whose rates replace the market's default rates.
+    #[serde(default)]
+    pub user_tiers: HashMap<Uuid, FeeRates>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule {
+            rates: FeeRates::default(),
+            fee_scale: Self::default_fee_scale(),
+            user_tiers: HashMap::new(),
+        }
+    }
+}
+
+impl FeeSchedule {
+    fn default_fee_scale() -> u32 {
+        8
+    }
+
+    pub fn rates_for(&self, user_id: Uuid) -> FeeRates {
+        self.user_tiers.get(&user_id).copied().unwrap_or(self.rates)
+    }
+
+    /// `amount * bps / 10_000`, rounded as described on the type. An amount too large to
+    /// multiply by the rate first is divided first instead, and saturates past that.
+    pub fn fee(&self, amount: Decimal, bps: Decimal) -> Decimal {
+        let fee = match amount.checked_mul(bps) {
+            Some(product) => product / dec!(10000),
+            None => (amount / dec!(10000)).saturating_mul(bps),
+        };
+        fee.round_dp_with_strategy(self.fee_scale, RoundingStrategy::ToPositiveInfinity)
+    }
+}
+
+/// Why the engine refused an order.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "reason")]
+pub enum RejectReason {
+    NonPositivePrice {
+        #[serde(with = "rust_decimal::serde::str")]
+        price: Decimal,
+    },
+    NonPositiveQuantity {
+        #[serde(with = "rust_decimal::serde::str")]
+        quantity: Decimal,
+    },
+    MissingStopPrice,
+    PriceNotOnTick {
+        #[serde(with = "rust_decimal::serde::str")]
+        price: Decimal,
+        #[serde(with = "rust_decimal::serde::str")]
+        tick_size: Decimal,
+    },
+    QuantityNotOnLot {
+        #[serde(with = "rust_decimal::serde::str")]
+        quantity: Decimal,
+        #[serde(with = "rust_decimal::serde::str")]
+        lot_size: Decimal,
+    },
+    QuantityBelowMinimum {
+        #[serde(with = "rust_decimal::serde::str")]
+        quantity: Decimal,
+        #[serde(with = "rust_decimal::serde::str")]
+        min_quantity: Decimal,
+    },
+    QuantityAboveMaximum {
+        #[serde(with = "rust_decimal::serde::str")]
+        quantity: Decimal,
+        #[serde(with = "rust_decimal::serde::str")]
+        max_quantity: Decimal,
+    },
+    NotionalBelowMinimum {
+        #[serde(with = "rust_decimal::serde::str")]
+        notional: Decimal,
+        #[serde(with = "rust_decimal::serde::str")]
+        min_notional: Decimal,
+    },
+    /// `price * quantity` does not fit in a `Decimal`.
+    NotionalOverflow {
+        #[serde(with = "rust_decimal::serde::str")]
+        price: Decimal,
+        #[serde(with = "rust_decimal::serde::str")]
+        quantity: Decimal,
+    },
+    /// A post-only order would take liquidity at its amended price.
+    PostOnlyWouldCross {
+        #[serde(with = "rust_decimal::serde::str")]
+        best_opposite_price: Decimal,
+    },
+    /// The market's status does not allow the order or command.
+    MarketStatus {
+        status: MarketStatus,
+    },
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::NonPositivePrice { price } => {
+                write!(f, "price {} must be positive", price)
+            }
+            RejectReason::NonPositiveQuantity { quantity } => {
+                write!(f, "quantity {} must be positive", quantity)
+            }
+            RejectReason::MissingStopPrice => write!(f, "stop order has no stop_price"),
+            RejectReason::PriceNotOnTick { price, tick_size } => {
+                write!(
+                    f,
+                    "price {} is not a multiple of tick size {}",
+                    price, tick_size
+                )
+            }
+            RejectReason::QuantityNotOnLot { quantity, lot_size } => {
+                write!(
+                    f,
+                    "quantity {} is not a multiple of lot size {}",
+                    quantity, lot_size
+                )
+            }
+            RejectReason::QuantityBelowMinimum {
+                quantity,
+                min_quantity,
+            } => write!(f, "quantity {} is below minimum {}", quantity, min_quantity),
+            RejectReason::QuantityAboveMaximum {
+                quantity,
+                max_quantity,
+            } => write!(f, "quantity {} is above maximum {}", quantity, max_quantity),
+            RejectReason::NotionalBelowMinimum {
+                notional,
+                min_notional,
+            } => write!(f, "notional {} is below minimum {}", notional, min_notional),
+            RejectReason::NotionalOverflow { price, quantity } => {
+                write!(f, "notional of {} at {} is too large", quantity, price)
+            }
+            RejectReason::PostOnlyWouldCross {
+                best_opposite_price,
+            } => write!(
+                f,
+                "post-only order would cross the best opposite price {}",
+                best_opposite_price
+            ),
+            RejectReason::MarketStatus { status } => {
+                write!(f, "not allowed while the market is {:?}", status)
+            }
+        }
+    }
+}
+
+/// Why an engine operation on a specific order or command failed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "error")]
+pub enum EngineError {
+    /// No live or pending order with this id.
+    OrderNotFound { order_id: Uuid },
+    /// The order is indexed but missing from its price level or stop queue.
+    OrderIndexMismatch { order_id: Uuid },
+    /// The order or amendment broke a validation rule.
+    Rejected {
+        #[serde(flatten)]
+        reason: RejectReason,
+    },
+    /// The command could not be parsed.
+    MalformedCommand { message: String },
+    /// The command could not be written to the journal, so it was not applied.
+    JournalUnavailable { message: String },
+    /// The command is only accepted from the market's admin queue or stream, and came
+    /// from `origin`.
+    AdminOnly { origin: String },
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::OrderNotFound { order_id } => write!(f, "order {} not found", order_id),
+            EngineError::OrderIndexMismatch { order_id } => {
+                write!(f, "order {} is indexed but not in the book", order_id)
+            }
+            EngineError::Rejected { reason } => write!(f, "rejected: {}", reason),
+            EngineError::MalformedCommand { message } => {
+                write!(f, "malformed command: {}", message)
+            }
+            EngineError::JournalUnavailable { message } => {
+                write!(f, "journal unavailable: {}", message)
+            }
+            EngineError::AdminOnly { origin } => {
+                write!(f, "only accepted from the admin queue, not '{}'", origin)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<RejectReason> for EngineError {
+    fn from(reason: RejectReason) -> Self {
+        EngineError::Rejected { reason }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "outcome")]
+pub enum PostOnlyOutcome {
+    /// The order did not cross and was posted at its own price.
+    Posted,
+    /// The order would have crossed and was dropped without touching the book.
+    Rejected {
+        #[serde(with = "rust_decimal::serde::str")]
+        best_opposite_price: Decimal,
+    },
+    /// The order would have crossed and was moved one tick behind the best opposite price.
+    Repriced {
+        #[serde(with = "rust_decimal::serde::str")]
+        original_price: Decimal,
+        #[serde(with = "rust_decimal::serde::str")]
+        new_price: Decimal,
+    },
+}
+
+/// Lifecycle state carried by an `ExecutionReport`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionStatus {
+    /// The order passed validation and is working (resting or parked as a stop).
+    Accepted,
+    PartiallyFilled,
+    Filled,
+    /// Removed by a cancel command or by self-trade prevention.
+    Canceled,
+    /// Refused by validation, post-only or fill-or-kill before it ever worked.
+    Rejected,
+    /// An IOC or market remainder that could not be filled.
+    Expired,
+    /// Price or quantity changed by an amendment or a self-trade decrement.
+    Replaced,
+}
+
+impl ExecutionStatus {
+    fn is_final(self) -> bool {
+        matches!(
+            self,
+            ExecutionStatus::Filled
+                | ExecutionStatus::Canceled
+                | ExecutionStatus::Rejected
+                | ExecutionStatus::Expired
+        )
+    }
+}
+
+/// Snapshot of an order's state after one lifecycle change.
+///
+/// `quantity` is the total ordered quantity, `remaining_quantity` what is still working in
+/// the book (zero once the order reaches a final status).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExecutionReport {
+    pub order_id: Uuid,
+    pub client_order_id: Option<String>,
+    pub user_id: Uuid,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub status: ExecutionStatus,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub price: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub quantity: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub filled_quantity: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub remaining_quantity: Decimal,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub average_price: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub last_fill_price: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub last_fill_quantity: Option<Decimal>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Per-order notifications that are not trades or book deltas.
+/// Collected while a command is processed and drained with `MatchingEngine::take_events`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "event", content = "data")]
+pub enum EngineEvent {
+    /// The order failed validation and never reached the book.
+    OrderRejected {
+        order_id: Uuid,
+        user_id: Uuid,
+        client_order_id: Option<String>,
+        #[serde(flatten)]
+        reason: RejectReason,
+    },
+    PostOnly {
+        order_id: Uuid,
+        #[serde(flatten)]
+        outcome: PostOnlyOutcome,
+    },
+    /// A cancel, amend or unparseable command was refused.
+    CommandRejected {
+        command: String,
+        order_id: Option<Uuid>,
+        client_order_id: Option<String>,
+        #[serde(flatten)]
+        error: EngineError,
+    },
+    /// The user already submitted an order with this `client_order_id`; the new one was ignored.
+    DuplicateOrder {
+        order_id: Uuid,
+        user_id: Uuid,
+        client_order_id: String,
+    },
+    /// A taker met a resting order from the same user; quantities were canceled instead of traded.
+    SelfTradePrevented {
+        mode: SelfTradePrevention,
+        taker_order_id: Uuid,
+        maker_order_id: Uuid,
+        #[serde(with = "rust_decimal::serde::str")]
+        taker_canceled_quantity: Decimal,
+        #[serde(with = "rust_decimal::serde::str")]
+        maker_canceled_quantity: Decimal,
+    },
+    /// The market was switched to another `MarketStatus` by an admin command.
+    MarketStatusChanged {
+        symbol: String,
+        previous: MarketStatus,
+        status: MarketStatus,
+    },
+    ExecutionReport(ExecutionReport),
 }
 
 // --- Internal Engine Structures ---
+/// Running totals for a working order, kept so execution reports carry cumulative figures.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OrderProgress {
+    user_id: Uuid,
+    client_order_id: Option<String>,
+    side: OrderSide,
+    order_type: OrderType,
+    price: Decimal,
+    quantity: Decimal,
+    filled_quantity: Decimal,
+    filled_notional: Decimal,
+    canceled_quantity: Decimal,
+}
+
+impl OrderProgress {
+    fn new(order: &Order) -> Self {
+        OrderProgress {
+            user_id: order.user_id,
+            client_order_id: order.client_order_id.clone(),
+            side: order.side,
+            order_type: order.order_type,
+            price: order.price,
+            quantity: order.quantity,
+            filled_quantity: Decimal::ZERO,
+            filled_notional: Decimal::ZERO,
+            canceled_quantity: Decimal::ZERO,
+        }
+    }
+
+    fn remaining(&self) -> Decimal {
+        self.quantity - self.filled_quantity - self.canceled_quantity
+    }
+
+    /// Adds a fill. The notional saturates rather than overflowing, which only skews the
+    /// average price of an order whose fills already exceed what a `Decimal` can hold.
+    fn fill(&mut self, price: Decimal, quantity: Decimal) {
+        self.filled_quantity += quantity;
+        self.filled_notional = self
+            .filled_notional
+            .saturating_add(price.saturating_mul(quantity));
+    }
+
+    /// Resizes the order so that `remaining` is what is left working.
+    fn resize(&mut self, price: Decimal, remaining: Decimal) {
+        self.price = price;
+        self.quantity = self.filled_quantity + self.canceled_quantity + remaining;
+    }
+
+    fn report(
+        &self,
+        order_id: Uuid,
+        status: ExecutionStatus,
+        last_fill: Option<(Decimal, Decimal)>,
+    ) -> ExecutionReport {
+        ExecutionReport {
+            order_id,
+            client_order_id: self.client_order_id.clone(),
+            user_id: self.user_id,
+            side: self.side,
+            order_type: self.order_type,
+            status,
+            price: self.price,
+            quantity: self.quantity,
+            filled_quantity: self.filled_quantity,
+            remaining_quantity: self.remaining(),
+            average_price: (!self.filled_quantity.is_zero())
+                .then(|| self.filled_notional / self.filled_quantity),
+            last_fill_price: last_fill.map(|(price, _)| price),
+            last_fill_quantity: last_fill.map(|(_, quantity)| quantity),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct PriceLevel {
     total_quantity: Decimal,
@@ -136,14 +773,86 @@ impl PriceLevel {
     }
 }
 
+/// The most recent client order ids of one user, oldest first.
+#[derive(Debug, Clone, Default)]
+struct ClientOrderIds {
+    order: VecDeque<String>,
+    seen: HashSet<String>,
+}
+
+impl ClientOrderIds {
+    fn insert(&mut self, client_order_id: &str, window: usize) {
+        if !self.seen.insert(client_order_id.to_string()) {
+            return;
+        }
+        self.order.push_back(client_order_id.to_string());
+        while self.order.len() > window {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// The complete L3 state of a `MatchingEngine`, used for on-disk snapshots.
+///
+/// Market configuration (rules, fees, self-trade prevention) is not part of the state;
+/// it is re-applied from the market config on startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineState {
+    symbol: String,
+    /// Resting orders per level, best price first, each level in FIFO order.
+    bids: Vec<(Decimal, Vec<Order>)>,
+    asks: Vec<(Decimal, Vec<Order>)>,
+    order_map: Vec<(Uuid, OrderSide, Decimal)>,
+    /// Pending stops per trigger price, next to fire first.
+    buy_stops: Vec<(Decimal, Vec<Order>)>,
+    sell_stops: Vec<(Decimal, Vec<Order>)>,
+    last_traded_price: Option<Decimal>,
+    fills: Vec<(Uuid, OrderProgress)>,
+    /// Each user's dedup window, oldest id first.
+    client_order_ids: Vec<(Uuid, Vec<String>)>,
+    sequence: u64,
+    status: MarketStatus,
+}
+
+impl EngineState {
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+}
+
 pub struct MatchingEngine {
     symbol: String,
     bids: BTreeMap<Reverse<Decimal>, PriceLevel>,
     asks: BTreeMap<Decimal, PriceLevel>,
     order_map: HashMap<Uuid, (OrderSide, Decimal)>,
+    // Pending stop orders, keyed by trigger price so the next one to fire is always first.
+    // Buy stops fire when the LTP rises to their trigger, sell stops when it falls to it.
+    buy_stops: BTreeMap<Decimal, VecDeque<Order>>,
+    sell_stops: BTreeMap<Reverse<Decimal>, VecDeque<Order>>,
+    stop_order_map: HashMap<Uuid, (OrderSide, Decimal)>,
     last_traded_price: Option<Decimal>,
+    rules: MarketRules,
+    // Market-wide self-trade prevention, used when an order does not set its own mode.
+    self_trade_prevention: Option<SelfTradePrevention>,
+    base_asset: String,
+    quote_asset: String,
+    fees: FeeSchedule,
+    // Cumulative fill state of every working order, dropped once the order reaches a final status.
+    fills: HashMap<Uuid, OrderProgress>,
+    // Per-user window of recently accepted client order ids, used to drop resubmissions.
+    client_order_ids: HashMap<Uuid, ClientOrderIds>,
+    dedup_window: usize,
+    // Last sequence number handed out to a trade or delta.
+    sequence: u64,
+    status: MarketStatus,
+    events: Vec<EngineEvent>,
 }
 
+/// How many client order ids are remembered per user unless configured otherwise.
+pub const DEFAULT_DEDUP_WINDOW: usize = 1000;
+
 // --- Core Engine Implementation ---
 impl MatchingEngine {
     pub fn new(symbol: String) -> Self {
@@ -152,17 +861,328 @@ impl MatchingEngine {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
             order_map: HashMap::new(),
+            buy_stops: BTreeMap::new(),
+            sell_stops: BTreeMap::new(),
+            stop_order_map: HashMap::new(),
             last_traded_price: None,
+            rules: MarketRules::default(),
+            self_trade_prevention: None,
+            base_asset: String::new(),
+            quote_asset: String::new(),
+            fees: FeeSchedule::default(),
+            fills: HashMap::new(),
+            client_order_ids: HashMap::new(),
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+            sequence: 0,
+            status: MarketStatus::Trading,
+            events: Vec::new(),
         }
     }
 
-    pub fn add_order(&mut self, order: Order) -> (Vec<Trade>, Vec<OrderBookDelta>) {
+    pub fn set_market_rules(&mut self, rules: MarketRules) {
+        self.rules = rules;
+    }
+
+    /// Checks an incoming order against basic sanity and the market's rules.
+    pub fn validate_order(&self, order: &Order) -> Result<(), RejectReason> {
+        self.rules.check_quantity(order.quantity)?;
+        if matches!(
+            order.order_type,
+            OrderType::StopLimit | OrderType::StopMarket
+        ) {
+            let stop_price = order.stop_price.ok_or(RejectReason::MissingStopPrice)?;
+            self.rules.check_price(stop_price)?;
+        }
+        // Market and stop-market orders carry no meaningful price.
+        if matches!(order.order_type, OrderType::Limit | OrderType::StopLimit) {
+            self.rules.check_price(order.price)?;
+            self.rules.check_notional(order.price, order.quantity)?;
+        }
+        Ok(())
+    }
+
+    /// Checks what an amendment changes against the rules a new order would have to pass:
+    /// the price rules for a new price, the quantity and notional rules for a new quantity.
+    /// What it leaves alone is not re-checked, so a partly filled order whose remainder is
+    /// below the minimums can still be repriced.
+    fn validate_amendment(
+        &self,
+        current: &Order,
+        new_price: Option<Decimal>,
+        new_quantity: Option<Decimal>,
+    ) -> Result<(), RejectReason> {
+        let priced = matches!(current.order_type, OrderType::Limit | OrderType::StopLimit);
+        let price = new_price.unwrap_or(current.price);
+        if let Some(price) = new_price
+            && priced
+        {
+            self.rules.check_price(price)?;
+        }
+        match new_quantity {
+            Some(quantity) => {
+                self.rules.check_quantity(quantity)?;
+                if priced {
+                    self.rules.check_notional(price, quantity)?;
+                }
+            }
+            None if priced => {
+                MarketRules::notional(price, current.quantity)?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub fn set_self_trade_prevention(&mut self, mode: Option<SelfTradePrevention>) {
+        self.self_trade_prevention = mode;
+    }
+
+    /// Sets how many client order ids are remembered per user for duplicate detection.
+    pub fn set_dedup_window(&mut self, window: usize) {
+        self.dedup_window = window;
+    }
+
+    /// Sets the assets fees are charged in.
+    pub fn set_assets(&mut self, base_asset: String, quote_asset: String) {
+        self.base_asset = base_asset;
+        self.quote_asset = quote_asset;
+    }
+
+    pub fn set_fee_schedule(&mut self, fees: FeeSchedule) {
+        self.fees = fees;
+    }
+
+    pub fn status(&self) -> MarketStatus {
+        self.status
+    }
+
+    /// Switches the market to `status` and reports the change. Resting orders are left in
+    /// the book whatever the new status. Setting the current status again does nothing.
+    pub fn set_status(&mut self, status: MarketStatus) {
+        if status == self.status {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.status, status);
+        self.events.push(EngineEvent::MarketStatusChanged {
+            symbol: self.symbol.clone(),
+            previous,
+            status,
+        });
+    }
+
+    /// Refuses what the market's status does not allow, and makes limit orders post-only
+    /// while the market is `PostOnly`.
+    fn apply_status(&self, order: &mut Order) -> Result<(), RejectReason> {
+        let refused = RejectReason::MarketStatus {
+            status: self.status,
+        };
+        match self.status {
+            MarketStatus::Trading => Ok(()),
+            MarketStatus::PostOnly if order.order_type == OrderType::Limit => {
+                order.post_only = Some(order.post_only.unwrap_or(PostOnlyMode::Reject));
+                Ok(())
+            }
+            MarketStatus::PostOnly | MarketStatus::CancelOnly | MarketStatus::Halted => {
+                Err(refused)
+            }
+        }
+    }
+
+    fn charge_fees(&self, trade: &mut Trade) {
+        let (maker_user_id, taker_user_id) = match trade.taker_side {
+            OrderSide::Buy => (trade.seller_user_id, trade.buyer_user_id),
+            OrderSide::Sell => (trade.buyer_user_id, trade.seller_user_id),
+        };
+        let maker_bps = self.fees.rates_for(maker_user_id).maker_fee_bps;
+        let taker_bps = self.fees.rates_for(taker_user_id).taker_fee_bps;
+        let base_fee = |bps| (self.fees.fee(trade.quantity, bps), self.base_asset.clone());
+        let quote_fee = |bps| {
+            let notional = trade.price.saturating_mul(trade.quantity);
+            (self.fees.fee(notional, bps), self.quote_asset.clone())
+        };
+        let ((maker_fee, maker_fee_asset), (taker_fee, taker_fee_asset)) = match trade.taker_side {
+            OrderSide::Buy => (quote_fee(maker_bps), base_fee(taker_bps)),
+            OrderSide::Sell => (base_fee(maker_bps), quote_fee(taker_bps)),
+        };
+        trade.maker_fee = maker_fee;
+        trade.maker_fee_asset = maker_fee_asset;
+        trade.taker_fee = taker_fee;
+        trade.taker_fee_asset = taker_fee_asset;
+    }
+
+    /// Drains the events produced since the last call.
+    pub fn take_events(&mut self) -> Vec<EngineEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Starts tracking `order` and reports it as accepted, unless it is already being tracked
+    /// (a triggered stop or an amended order re-entering matching).
+    fn accept(&mut self, order: &Order) {
+        if let Entry::Vacant(entry) = self.fills.entry(order.id) {
+            entry.insert(OrderProgress::new(order));
+            self.report(order.id, ExecutionStatus::Accepted);
+        }
+    }
+
+    fn reject(&mut self, order: &Order) {
+        self.fills
+            .entry(order.id)
+            .or_insert_with(|| OrderProgress::new(order));
+        self.report(order.id, ExecutionStatus::Rejected);
+    }
+
+    fn report(&mut self, order_id: Uuid, status: ExecutionStatus) {
+        report_execution(&mut self.fills, &mut self.events, order_id, status, None);
+    }
+
+    pub fn add_order(&mut self, mut order: Order) -> (Vec<Trade>, Vec<OrderBookDelta>) {
+        if let Some(client_order_id) = &order.client_order_id
+            && self
+                .client_order_ids
+                .get(&order.user_id)
+                .is_some_and(|ids| ids.seen.contains(client_order_id))
+        {
+            self.events.push(EngineEvent::DuplicateOrder {
+                order_id: order.id,
+                user_id: order.user_id,
+                client_order_id: client_order_id.clone(),
+            });
+            return (Vec::new(), Vec::new());
+        }
+        if let Err(reason) = self
+            .apply_status(&mut order)
+            .and_then(|_| self.validate_order(&order))
+        {
+            self.events.push(EngineEvent::OrderRejected {
+                order_id: order.id,
+                user_id: order.user_id,
+                client_order_id: order.client_order_id.clone(),
+                reason,
+            });
+            self.reject(&order);
+            return (Vec::new(), Vec::new());
+        }
+        if let Some(client_order_id) = &order.client_order_id {
+            self.client_order_ids
+                .entry(order.user_id)
+                .or_default()
+                .insert(client_order_id, self.dedup_window);
+        }
+        let (mut trades, mut deltas) = match order.order_type {
+            OrderType::StopLimit | OrderType::StopMarket => self.process_stop_order(order),
+            OrderType::Limit | OrderType::Market => self.execute_order(order),
+        };
+        // Any fills above may have moved the LTP through pending stops.
+        self.trigger_stop_orders(&mut trades, &mut deltas);
+        self.stamp(&mut trades, &mut deltas);
+        (trades, deltas)
+    }
+
+    /// Numbers a command's trades, then its deltas, continuing the market's sequence.
+    fn stamp(&mut self, trades: &mut [Trade], deltas: &mut [OrderBookDelta]) {
+        for trade in trades {
+            self.sequence += 1;
+            trade.sequence = self.sequence;
+        }
+        for delta in deltas {
+            self.sequence += 1;
+            delta.sequence = self.sequence;
+        }
+    }
+
+    /// Sequence number of the last trade or delta produced.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    fn execute_order(&mut self, order: Order) -> (Vec<Trade>, Vec<OrderBookDelta>) {
+        // FOK is all-or-nothing: check the opposite side before touching the book so a
+        // rejected order leaves no trades and no deltas behind.
+        if order.time_in_force == TimeInForce::Fok && !self.can_fill_completely(&order) {
+            self.reject(&order);
+            return (Vec::new(), Vec::new());
+        }
         match order.order_type {
             OrderType::Limit => self.process_limit_order(order),
             OrderType::Market => self.process_market_order(order),
+            OrderType::StopLimit | OrderType::StopMarket => {
+                unreachable!("stop orders are converted before execution")
+            }
         }
     }
+
+    fn process_stop_order(&mut self, order: Order) -> (Vec<Trade>, Vec<OrderBookDelta>) {
+        // `validate_order` guarantees stop orders carry a stop price.
+        let Some(stop_price) = order.stop_price else {
+            return (Vec::new(), Vec::new());
+        };
+
+        // A stop whose trigger has already been crossed fires straight away.
+        if is_stop_triggered(order.side, stop_price, self.last_traded_price) {
+            return self.execute_order(into_triggered_order(order));
+        }
+
+        self.accept(&order);
+        self.stop_order_map
+            .insert(order.id, (order.side, stop_price));
+        match order.side {
+            OrderSide::Buy => self
+                .buy_stops
+                .entry(stop_price)
+                .or_default()
+                .push_back(order),
+            OrderSide::Sell => self
+                .sell_stops
+                .entry(Reverse(stop_price))
+                .or_default()
+                .push_back(order),
+        }
+        (Vec::new(), Vec::new())
+    }
+
+    /// Fires every pending stop whose trigger has been crossed by the LTP. Fills from a
+    /// triggered order can move the LTP again, so this keeps going until nothing is left to fire.
+    fn trigger_stop_orders(&mut self, trades: &mut Vec<Trade>, deltas: &mut Vec<OrderBookDelta>) {
+        while let Some(order) = self.pop_triggered_stop() {
+            let (new_trades, new_deltas) = self.execute_order(into_triggered_order(order));
+            trades.extend(new_trades);
+            deltas.extend(new_deltas);
+        }
+    }
+
+    fn pop_triggered_stop(&mut self) -> Option<Order> {
+        let ltp = self.last_traded_price;
+        let order = if let Some(mut entry) = self.buy_stops.first_entry()
+            && is_stop_triggered(OrderSide::Buy, *entry.key(), ltp)
+        {
+            let order = entry.get_mut().pop_front();
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            order
+        } else if let Some(mut entry) = self.sell_stops.first_entry()
+            && is_stop_triggered(OrderSide::Sell, entry.key().0, ltp)
+        {
+            let order = entry.get_mut().pop_front();
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            order
+        } else {
+            None
+        }?;
+        self.stop_order_map.remove(&order.id);
+        Some(order)
+    }
+
     pub fn get_order_by_id(&self, order_id: Uuid) -> Option<Order> {
+        if let Some((side, stop_price)) = self.stop_order_map.get(&order_id) {
+            let queue = match side {
+                OrderSide::Buy => self.buy_stops.get(stop_price),
+                OrderSide::Sell => self.sell_stops.get(&Reverse(*stop_price)),
+            };
+            return queue.and_then(|q| q.iter().find(|o| o.id == order_id).cloned());
+        }
         if let Some((side, price)) = self.order_map.get(&order_id) {
             let book = match side {
                 OrderSide::Buy => self.bids.get(&Reverse(*price)),
@@ -175,63 +1195,226 @@ impl MatchingEngine {
         None
     }
 
-    pub fn cancel_order(&mut self, order_id: Uuid) -> (Result<Order, String>, Vec<OrderBookDelta>) {
+    pub fn cancel_order(
+        &mut self,
+        order_id: Uuid,
+    ) -> (Result<Order, EngineError>, Vec<OrderBookDelta>) {
+        if self.status == MarketStatus::Halted {
+            let refused = RejectReason::MarketStatus {
+                status: self.status,
+            };
+            return (Err(refused.into()), Vec::new());
+        }
+        let (removed, mut deltas) = self.remove_order(order_id);
+        if removed.is_ok() {
+            self.report(order_id, ExecutionStatus::Canceled);
+        }
+        self.stamp(&mut [], &mut deltas);
+        (removed, deltas)
+    }
+
+    /// Takes an order out of the book or the stop queues without reporting it as canceled.
+    fn remove_order(
+        &mut self,
+        order_id: Uuid,
+    ) -> (Result<Order, EngineError>, Vec<OrderBookDelta>) {
         let mut deltas = Vec::new();
+        // Pending stops are not part of the visible book, so cancelling one emits no deltas.
+        if let Some((side, stop_price)) = self.stop_order_map.remove(&order_id) {
+            let removed = match side {
+                OrderSide::Buy => remove_stop(&mut self.buy_stops, stop_price, order_id),
+                OrderSide::Sell => remove_stop(&mut self.sell_stops, Reverse(stop_price), order_id),
+            };
+            return match removed {
+                Some(order) => (Ok(order), deltas),
+                None => (Err(EngineError::OrderIndexMismatch { order_id }), deltas),
+            };
+        }
         if let Some((side, price)) = self.order_map.remove(&order_id) {
             let book = match side {
                 OrderSide::Buy => self.bids.get_mut(&Reverse(price)),
                 OrderSide::Sell => self.asks.get_mut(&price),
             };
 
-            if let Some(level) = book {
-                if let Some(pos) = level.orders.iter().position(|o| o.id == order_id) {
-                    let removed_order = level.orders.remove(pos).unwrap();
-                    level.total_quantity -= removed_order.quantity;
-
-                    if level.orders.is_empty() {
-                        deltas.push(OrderBookDelta {
-                            action: DeltaAction::Delete,
-                            side,
-                            price,
-                            new_quantity: Decimal::ZERO,
-                        });
-                        if side == OrderSide::Buy {
-                            self.bids.remove(&Reverse(price));
-                        } else {
-                            self.asks.remove(&price);
-                        }
+            if let Some(level) = book
+                && let Some(pos) = level.orders.iter().position(|o| o.id == order_id)
+            {
+                let removed_order = level.orders.remove(pos).unwrap();
+                level.total_quantity -= removed_order.quantity;
+
+                if level.orders.is_empty() {
+                    deltas.push(OrderBookDelta {
+                        action: DeltaAction::Delete,
+                        side,
+                        price,
+                        new_quantity: Decimal::ZERO,
+                        sequence: 0,
+                    });
+                    if side == OrderSide::Buy {
+                        self.bids.remove(&Reverse(price));
                     } else {
-                        deltas.push(OrderBookDelta {
-                            action: DeltaAction::Update,
-                            side,
-                            price,
-                            new_quantity: level.total_quantity,
-                        });
+                        self.asks.remove(&price);
                     }
-                    return (Ok(removed_order), deltas);
+                } else {
+                    deltas.push(OrderBookDelta {
+                        action: DeltaAction::Update,
+                        side,
+                        price,
+                        new_quantity: level.total_quantity,
+                        sequence: 0,
+                    });
                 }
+                return (Ok(removed_order), deltas);
             }
-            (
-                Err(format!(
-                    "[Engine] Order {} in map but not in book.",
-                    order_id
-                )),
-                deltas,
-            )
+            (Err(EngineError::OrderIndexMismatch { order_id }), deltas)
         } else {
-            (
-                Err(format!(
-                    "[Engine] Order {} not found for cancellation.",
-                    order_id
-                )),
-                deltas,
-            )
+            (Err(EngineError::OrderNotFound { order_id }), deltas)
+        }
+    }
+
+    /// Changes the price and/or remaining quantity of a live order.
+    ///
+    /// Reducing the quantity at the same price keeps the order's place in its `PriceLevel`
+    /// queue. Any price change or quantity increase moves it to the back of the queue at the
+    /// target price and re-runs matching, exactly as if it had just arrived.
+    pub fn amend_order(
+        &mut self,
+        order_id: Uuid,
+        new_price: Option<Decimal>,
+        new_quantity: Option<Decimal>,
+    ) -> (Result<Order, EngineError>, Vec<Trade>, Vec<OrderBookDelta>) {
+        let (amended, mut trades, mut deltas) = self.amend(order_id, new_price, new_quantity);
+        self.stamp(&mut trades, &mut deltas);
+        (amended, trades, deltas)
+    }
+
+    fn amend(
+        &mut self,
+        order_id: Uuid,
+        new_price: Option<Decimal>,
+        new_quantity: Option<Decimal>,
+    ) -> (Result<Order, EngineError>, Vec<Trade>, Vec<OrderBookDelta>) {
+        let refused = RejectReason::MarketStatus {
+            status: self.status,
+        };
+        if matches!(self.status, MarketStatus::CancelOnly | MarketStatus::Halted) {
+            return (Err(refused.into()), Vec::new(), Vec::new());
+        }
+        let Some(current) = self.get_order_by_id(order_id) else {
+            return (
+                Err(EngineError::OrderNotFound { order_id }),
+                Vec::new(),
+                Vec::new(),
+            );
+        };
+        if let Err(reason) = self.validate_amendment(&current, new_price, new_quantity) {
+            return (Err(reason.into()), Vec::new(), Vec::new());
+        }
+
+        if let Some((side, stop_price)) = self.stop_order_map.get(&order_id).copied() {
+            let queue = match side {
+                OrderSide::Buy => self.buy_stops.get_mut(&stop_price),
+                OrderSide::Sell => self.sell_stops.get_mut(&Reverse(stop_price)),
+            };
+            return match queue.and_then(|q| q.iter_mut().find(|o| o.id == order_id)) {
+                Some(order) => {
+                    order.price = new_price.unwrap_or(order.price);
+                    order.quantity = new_quantity.unwrap_or(order.quantity);
+                    let amended = order.clone();
+                    self.report_replaced(&amended);
+                    (Ok(amended), Vec::new(), Vec::new())
+                }
+                None => (
+                    Err(EngineError::OrderIndexMismatch { order_id }),
+                    Vec::new(),
+                    Vec::new(),
+                ),
+            };
+        }
+
+        let Some((side, price)) = self.order_map.get(&order_id).copied() else {
+            return (
+                Err(EngineError::OrderNotFound { order_id }),
+                Vec::new(),
+                Vec::new(),
+            );
+        };
+        let target_price = new_price.unwrap_or(price);
+        if self.status == MarketStatus::PostOnly && self.crosses(side, target_price) {
+            return (Err(refused.into()), Vec::new(), Vec::new());
+        }
+        // Checked before the order is pulled from the book, so a refused amend leaves it
+        // resting untouched.
+        if current.post_only == Some(PostOnlyMode::Reject)
+            && self.crosses(side, target_price)
+            && let Some(best_opposite_price) = self.best_opposite(side)
+        {
+            let reason = RejectReason::PostOnlyWouldCross {
+                best_opposite_price,
+            };
+            return (Err(reason.into()), Vec::new(), Vec::new());
+        }
+
+        if target_price == price {
+            let level = match side {
+                OrderSide::Buy => self.bids.get_mut(&Reverse(price)),
+                OrderSide::Sell => self.asks.get_mut(&price),
+            };
+            if let Some(level) = level
+                && let Some(order) = level.orders.iter_mut().find(|o| o.id == order_id)
+                && new_quantity.is_none_or(|q| q <= order.quantity)
+            {
+                let target_quantity = new_quantity.unwrap_or(order.quantity);
+                let reduced_by = order.quantity - target_quantity;
+                order.quantity = target_quantity;
+                let amended = order.clone();
+                level.total_quantity -= reduced_by;
+
+                let mut deltas = Vec::new();
+                if reduced_by > dec!(0) {
+                    deltas.push(OrderBookDelta {
+                        action: DeltaAction::Update,
+                        side,
+                        price,
+                        new_quantity: level.total_quantity,
+                        sequence: 0,
+                    });
+                }
+                self.report_replaced(&amended);
+                return (Ok(amended), Vec::new(), deltas);
+            }
+        }
+
+        // Everything else loses time priority: pull the order and run it through matching again.
+        let (removed, mut deltas) = self.remove_order(order_id);
+        let mut order = match removed {
+            Ok(order) => order,
+            Err(e) => return (Err(e), Vec::new(), deltas),
+        };
+        order.price = target_price;
+        order.quantity = new_quantity.unwrap_or(order.quantity);
+        order.timestamp = Utc::now();
+        let amended = order.clone();
+        self.report_replaced(&amended);
+
+        let (mut trades, new_deltas) = self.process_limit_order(order);
+        deltas.extend(new_deltas);
+        self.trigger_stop_orders(&mut trades, &mut deltas);
+        (Ok(amended), trades, deltas)
+    }
+
+    fn report_replaced(&mut self, amended: &Order) {
+        if let Some(progress) = self.fills.get_mut(&amended.id) {
+            progress.resize(amended.price, amended.quantity);
         }
+        self.report(amended.id, ExecutionStatus::Replaced);
     }
 
     fn process_market_order(&mut self, mut order: Order) -> (Vec<Trade>, Vec<OrderBookDelta>) {
         let mut trades = Vec::new();
         let mut deltas = Vec::new();
+        let stp = order.self_trade_prevention.or(self.self_trade_prevention);
+        self.accept(&order);
 
         match order.side {
             OrderSide::Buy => {
@@ -243,8 +1426,16 @@ impl MatchingEngine {
                     }
                     if let Some(level) = self.asks.get_mut(&price) {
                         // FINAL FIX: Pass disjoint fields `self.order_map` and `level` to the free function.
-                        let (new_trades, new_deltas) =
-                            process_level(&mut self.order_map, &mut order, level, price);
+                        let (new_trades, new_deltas) = process_level(
+                            &self.symbol,
+                            &mut self.order_map,
+                            &mut self.fills,
+                            &mut self.events,
+                            &mut order,
+                            level,
+                            price,
+                            stp,
+                        );
                         trades.extend(new_trades);
                         deltas.extend(new_deltas);
                         if level.orders.is_empty() {
@@ -265,8 +1456,16 @@ impl MatchingEngine {
                     }
                     if let Some(level) = self.bids.get_mut(&price_rev) {
                         // FINAL FIX: Pass disjoint fields `self.order_map` and `level` to the free function.
-                        let (new_trades, new_deltas) =
-                            process_level(&mut self.order_map, &mut order, level, price_rev.0);
+                        let (new_trades, new_deltas) = process_level(
+                            &self.symbol,
+                            &mut self.order_map,
+                            &mut self.fills,
+                            &mut self.events,
+                            &mut order,
+                            level,
+                            price_rev.0,
+                            stp,
+                        );
                         trades.extend(new_trades);
                         deltas.extend(new_deltas);
                         if level.orders.is_empty() {
@@ -280,6 +1479,13 @@ impl MatchingEngine {
             }
         }
 
+        // Market orders never rest; whatever the book could not fill is dropped.
+        if order.quantity > dec!(0) {
+            self.report(order.id, ExecutionStatus::Expired);
+        }
+        for trade in &mut trades {
+            self.charge_fees(trade);
+        }
         if let Some(last_trade) = trades.last() {
             self.last_traded_price = Some(last_trade.price);
         }
@@ -290,6 +1496,21 @@ impl MatchingEngine {
         let mut trades = Vec::new();
         let mut deltas = Vec::new();
 
+        if let Some(mode) = order.post_only {
+            let outcome = self.apply_post_only(&mut order, mode);
+            let rejected = matches!(outcome, PostOnlyOutcome::Rejected { .. });
+            self.events.push(EngineEvent::PostOnly {
+                order_id: order.id,
+                outcome,
+            });
+            if rejected {
+                self.reject(&order);
+                return (trades, deltas);
+            }
+        }
+
+        let stp = order.self_trade_prevention.or(self.self_trade_prevention);
+        self.accept(&order);
         self.order_map.insert(order.id, (order.side, order.price));
 
         match order.side {
@@ -304,8 +1525,16 @@ impl MatchingEngine {
                     }
                     if let Some(level) = self.asks.get_mut(&price) {
                         // FINAL FIX: Pass disjoint fields `self.order_map` and `level` to the free function.
-                        let (new_trades, new_deltas) =
-                            process_level(&mut self.order_map, &mut order, level, price);
+                        let (new_trades, new_deltas) = process_level(
+                            &self.symbol,
+                            &mut self.order_map,
+                            &mut self.fills,
+                            &mut self.events,
+                            &mut order,
+                            level,
+                            price,
+                            stp,
+                        );
                         trades.extend(new_trades);
                         deltas.extend(new_deltas);
                         if level.orders.is_empty() {
@@ -318,7 +1547,7 @@ impl MatchingEngine {
                     self.asks.remove(&price);
                 }
 
-                if order.quantity > dec!(0) {
+                if order.quantity > dec!(0) && order.time_in_force == TimeInForce::Gtc {
                     let level = self
                         .bids
                         .entry(Reverse(order.price))
@@ -335,9 +1564,13 @@ impl MatchingEngine {
                         side: OrderSide::Buy,
                         price: level.orders[0].price,
                         new_quantity: level.total_quantity,
+                        sequence: 0,
                     });
                 } else {
                     self.order_map.remove(&order.id);
+                    if order.quantity > dec!(0) {
+                        self.report(order.id, ExecutionStatus::Expired);
+                    }
                 }
             }
             OrderSide::Sell => {
@@ -354,8 +1587,16 @@ impl MatchingEngine {
                     }
                     if let Some(level) = self.bids.get_mut(&price_rev) {
                         // FINAL FIX: Pass disjoint fields `self.order_map` and `level` to the free function.
-                        let (new_trades, new_deltas) =
-                            process_level(&mut self.order_map, &mut order, level, price_rev.0);
+                        let (new_trades, new_deltas) = process_level(
+                            &self.symbol,
+                            &mut self.order_map,
+                            &mut self.fills,
+                            &mut self.events,
+                            &mut order,
+                            level,
+                            price_rev.0,
+                            stp,
+                        );
                         trades.extend(new_trades);
                         deltas.extend(new_deltas);
                         if level.orders.is_empty() {
@@ -368,7 +1609,7 @@ impl MatchingEngine {
                     self.bids.remove(&price_rev);
                 }
 
-                if order.quantity > dec!(0) {
+                if order.quantity > dec!(0) && order.time_in_force == TimeInForce::Gtc {
                     let level = self.asks.entry(order.price).or_insert_with(PriceLevel::new);
                     let is_new_level = level.orders.is_empty();
                     level.total_quantity += order.quantity;
@@ -382,19 +1623,121 @@ impl MatchingEngine {
                         side: OrderSide::Sell,
                         price: level.orders[0].price,
                         new_quantity: level.total_quantity,
+                        sequence: 0,
                     });
                 } else {
                     self.order_map.remove(&order.id);
+                    if order.quantity > dec!(0) {
+                        self.report(order.id, ExecutionStatus::Expired);
+                    }
                 }
             }
         }
 
+        for trade in &mut trades {
+            self.charge_fees(trade);
+        }
         if let Some(last_trade) = trades.last() {
             self.last_traded_price = Some(last_trade.price);
         }
         (trades, deltas)
     }
 
+    /// Best price on the side of the book a `side` order would take from.
+    fn best_opposite(&self, side: OrderSide) -> Option<Decimal> {
+        match side {
+            OrderSide::Buy => self.asks.keys().next().copied(),
+            OrderSide::Sell => self.bids.keys().next().map(|p| p.0),
+        }
+    }
+
+    /// True if a `side` order at `price` would take from the opposite side of the book.
+    fn crosses(&self, side: OrderSide, price: Decimal) -> bool {
+        self.best_opposite(side).is_some_and(|best| match side {
+            OrderSide::Buy => price >= best,
+            OrderSide::Sell => price <= best,
+        })
+    }
+
+    /// Decides whether a post-only order can rest as-is. In `Reprice` mode a crossing order
+    /// has its price moved one tick behind the best opposite price so it can never take.
+    fn apply_post_only(&self, order: &mut Order, mode: PostOnlyMode) -> PostOnlyOutcome {
+        let Some(best_opposite_price) = self.best_opposite(order.side) else {
+            return PostOnlyOutcome::Posted;
+        };
+        let crosses = match order.side {
+            OrderSide::Buy => order.price >= best_opposite_price,
+            OrderSide::Sell => order.price <= best_opposite_price,
+        };
+        if !crosses {
+            return PostOnlyOutcome::Posted;
+        }
+
+        // Without a configured tick size, use the smallest increment at the price's scale.
+        let tick = self
+            .rules
+            .tick_size
+            .unwrap_or_else(|| Decimal::new(1, best_opposite_price.scale()));
+        let new_price = match order.side {
+            OrderSide::Buy => best_opposite_price - tick,
+            OrderSide::Sell => best_opposite_price + tick,
+        };
+        if mode == PostOnlyMode::Reject || new_price <= dec!(0) {
+            return PostOnlyOutcome::Rejected {
+                best_opposite_price,
+            };
+        }
+
+        let original_price = order.price;
+        order.price = new_price;
+        PostOnlyOutcome::Repriced {
+            original_price,
+            new_price,
+        }
+    }
+
+    /// Returns true if the opposite side holds enough quantity at acceptable prices
+    /// to fill `order` in full. Limit orders only count levels at or better than their price.
+    /// With self-trade prevention active, the user's own resting orders never fill the taker:
+    /// under `CancelOldest` they are skipped, under every other mode they cut the taker short.
+    fn can_fill_completely(&self, order: &Order) -> bool {
+        let stp = order.self_trade_prevention.or(self.self_trade_prevention);
+        let levels: Box<dyn Iterator<Item = (Decimal, &PriceLevel)>> = match order.side {
+            OrderSide::Buy => Box::new(self.asks.iter().map(|(p, l)| (*p, l))),
+            OrderSide::Sell => Box::new(self.bids.iter().map(|(p, l)| (p.0, l))),
+        };
+
+        let mut remaining = order.quantity;
+        for (price, level) in levels {
+            let price_ok = match order.side {
+                OrderSide::Buy => price <= order.price,
+                OrderSide::Sell => price >= order.price,
+            };
+            if order.order_type == OrderType::Limit && !price_ok {
+                break;
+            }
+            match stp {
+                None => remaining -= level.total_quantity,
+                Some(mode) => {
+                    for maker in &level.orders {
+                        if remaining <= dec!(0) {
+                            break;
+                        }
+                        if maker.user_id != order.user_id {
+                            remaining -= maker.quantity;
+                        } else if mode != SelfTradePrevention::CancelOldest {
+                            return false;
+                        }
+                    }
+                }
+            }
+            if remaining <= dec!(0) {
+                break;
+            }
+        }
+        remaining <= dec!(0)
+    }
+
     pub fn get_order_book_snapshot(&self) -> OrderBookSnapshot {
         let bids = self
             .bids
@@ -420,25 +1763,190 @@ impl MatchingEngine {
             asks,
             last_traded_price: self.last_traded_price,
             timestamp: Utc::now(),
+            sequence: self.sequence,
+            status: self.status,
         }
     }
     pub fn get_last_traded_price(&self) -> Option<Decimal> {
         self.last_traded_price
     }
+
+    pub fn export_state(&self) -> EngineState {
+        let level_orders = |level: &PriceLevel| level.orders.iter().cloned().collect();
+        let stop_orders = |queue: &VecDeque<Order>| queue.iter().cloned().collect();
+        EngineState {
+            symbol: self.symbol.clone(),
+            bids: self
+                .bids
+                .iter()
+                .map(|(price, level)| (price.0, level_orders(level)))
+                .collect(),
+            asks: self
+                .asks
+                .iter()
+                .map(|(price, level)| (*price, level_orders(level)))
+                .collect(),
+            order_map: self
+                .order_map
+                .iter()
+                .map(|(id, (side, price))| (*id, *side, *price))
+                .collect(),
+            buy_stops: self
+                .buy_stops
+                .iter()
+                .map(|(price, queue)| (*price, stop_orders(queue)))
+                .collect(),
+            sell_stops: self
+                .sell_stops
+                .iter()
+                .map(|(price, queue)| (price.0, stop_orders(queue)))
+                .collect(),
+            last_traded_price: self.last_traded_price,
+            fills: self
+                .fills
+                .iter()
+                .map(|(id, progress)| (*id, progress.clone()))
+                .collect(),
+            client_order_ids: self
+                .client_order_ids
+                .iter()
+                .map(|(user_id, ids)| (*user_id, ids.order.iter().cloned().collect()))
+                .collect(),
+            sequence: self.sequence,
+            status: self.status,
+        }
+    }
+
+    /// Replaces the book with `state`. Market configuration is left as it is.
+    pub fn restore_state(&mut self, state: EngineState) {
+        let level = |orders: Vec<Order>| PriceLevel {
+            total_quantity: orders.iter().map(|o| o.quantity).sum(),
+            orders: orders.into(),
+        };
+        self.bids = state
+            .bids
+            .into_iter()
+            .map(|(price, orders)| (Reverse(price), level(orders)))
+            .collect();
+        self.asks = state
+            .asks
+            .into_iter()
+            .map(|(price, orders)| (price, level(orders)))
+            .collect();
+        self.order_map = state
+            .order_map
+            .into_iter()
+            .map(|(id, side, price)| (id, (side, price)))
+            .collect();
+        self.stop_order_map.clear();
+        for (price, orders) in &state.buy_stops {
+            for order in orders {
+                self.stop_order_map
+                    .insert(order.id, (OrderSide::Buy, *price));
+            }
+        }
+        for (price, orders) in &state.sell_stops {
+            for order in orders {
+                self.stop_order_map
+                    .insert(order.id, (OrderSide::Sell, *price));
+            }
+        }
+        self.buy_stops = state
+            .buy_stops
+            .into_iter()
+            .map(|(price, orders)| (price, orders.into()))
+            .collect();
+        self.sell_stops = state
+            .sell_stops
+            .into_iter()
+            .map(|(price, orders)| (Reverse(price), orders.into()))
+            .collect();
+        self.last_traded_price = state.last_traded_price;
+        self.fills = state.fills.into_iter().collect();
+        self.sequence = state.sequence;
+        self.status = state.status;
+        self.client_order_ids.clear();
+        for (user_id, ids) in state.client_order_ids {
+            let window = self.client_order_ids.entry(user_id).or_default();
+            for client_order_id in ids {
+                window.insert(&client_order_id, self.dedup_window);
+            }
+        }
+        self.events.clear();
+    }
+}
+
+fn is_stop_triggered(side: OrderSide, stop_price: Decimal, ltp: Option<Decimal>) -> bool {
+    match (side, ltp) {
+        (OrderSide::Buy, Some(ltp)) => ltp >= stop_price,
+        (OrderSide::Sell, Some(ltp)) => ltp <= stop_price,
+        (_, None) => false,
+    }
+}
+
+fn into_triggered_order(mut order: Order) -> Order {
+    order.order_type = match order.order_type {
+        OrderType::StopLimit => OrderType::Limit,
+        OrderType::StopMarket => OrderType::Market,
+        other => other,
+    };
+    order
+}
+
+/// Pushes an execution report for a tracked order. A final status closes out whatever is
+/// still working and stops tracking the order.
+fn report_execution(
+    fills: &mut HashMap<Uuid, OrderProgress>,
+    events: &mut Vec<EngineEvent>,
+    order_id: Uuid,
+    status: ExecutionStatus,
+    last_fill: Option<(Decimal, Decimal)>,
+) {
+    let Some(progress) = fills.get_mut(&order_id) else {
+        return;
+    };
+    if status.is_final() {
+        progress.canceled_quantity += progress.remaining();
+    }
+    let report = progress.report(order_id, status, last_fill);
+    if status.is_final() {
+        fills.remove(&order_id);
+    }
+    events.push(EngineEvent::ExecutionReport(report));
+}
+
+fn remove_stop<K: Ord>(
+    stops: &mut BTreeMap<K, VecDeque<Order>>,
+    key: K,
+    order_id: Uuid,
+) -> Option<Order> {
+    let queue = stops.get_mut(&key)?;
+    let pos = queue.iter().position(|o| o.id == order_id)?;
+    let order = queue.remove(pos);
+    if queue.is_empty() {
+        stops.remove(&key);
+    }
+    order
 }
 
 // FINAL FIX: `process_level` is now a "free function", not a method.
 // It no longer takes `&mut self`. Instead, the specific, disjoint fields it needs
-// (`order_map` and `level`) are passed in directly. This resolves the borrow checker conflict.
+// (`order_map`, `fills`, `events` and `level`) are passed in directly. This resolves the borrow checker conflict.
+#[allow(clippy::too_many_arguments)]
 fn process_level(
+    symbol: &str,
     order_map: &mut HashMap<Uuid, (OrderSide, Decimal)>,
+    fills: &mut HashMap<Uuid, OrderProgress>,
+    events: &mut Vec<EngineEvent>,
     taker_order: &mut Order,
     level: &mut PriceLevel,
     trade_price: Decimal,
+    stp: Option<SelfTradePrevention>,
 ) -> (Vec<Trade>, Vec<OrderBookDelta>) {
     let mut trades = Vec::new();
     let mut deltas = Vec::new();
     let mut orders_to_remove_indices = Vec::new();
+    let initial_level_quantity = level.total_quantity;
     let maker_side = if taker_order.side == OrderSide::Buy {
         OrderSide::Sell
     } else {
@@ -449,11 +1957,62 @@ fn process_level(
         if taker_order.quantity <= dec!(0) {
             break;
         }
+
+        if let Some(mode) = stp
+            && maker_order.user_id == taker_order.user_id
+        {
+            let (taker_canceled_quantity, maker_canceled_quantity) = match mode {
+                SelfTradePrevention::CancelNewest => (taker_order.quantity, Decimal::ZERO),
+                SelfTradePrevention::CancelOldest => (Decimal::ZERO, maker_order.quantity),
+                SelfTradePrevention::CancelBoth => (taker_order.quantity, maker_order.quantity),
+                SelfTradePrevention::DecrementAndCancel => {
+                    let overlap = taker_order.quantity.min(maker_order.quantity);
+                    (overlap, overlap)
+                }
+            };
+
+            taker_order.quantity -= taker_canceled_quantity;
+            maker_order.quantity -= maker_canceled_quantity;
+            level.total_quantity -= maker_canceled_quantity;
+
+            if maker_order.quantity <= dec!(0) {
+                orders_to_remove_indices.push(idx);
+                order_map.remove(&maker_order.id);
+            }
+            events.push(EngineEvent::SelfTradePrevented {
+                mode,
+                taker_order_id: taker_order.id,
+                maker_order_id: maker_order.id,
+                taker_canceled_quantity,
+                maker_canceled_quantity,
+            });
+            for (order, canceled) in [
+                (&*maker_order, maker_canceled_quantity),
+                (&*taker_order, taker_canceled_quantity),
+            ] {
+                if canceled.is_zero() {
+                    continue;
+                }
+                // A partly decremented order keeps working with a smaller size.
+                let status = if order.quantity <= dec!(0) {
+                    ExecutionStatus::Canceled
+                } else {
+                    ExecutionStatus::Replaced
+                };
+                if let Some(progress) = fills.get_mut(&order.id) {
+                    progress.canceled_quantity += canceled;
+                }
+                report_execution(fills, events, order.id, status, None);
+            }
+            continue;
+        }
+
         let trade_quantity = taker_order.quantity.min(maker_order.quantity);
 
         trades.push(Trade::new(
-            taker_order.id,
-            maker_order.id,
+            symbol,
+            taker_order,
+            maker_order,
             trade_price,
             trade_quantity,
         ));
@@ -462,6 +2021,24 @@ fn process_level(
         maker_order.quantity -= trade_quantity;
         level.total_quantity -= trade_quantity;
 
+        for order in [&*taker_order, &*maker_order] {
+            let status = if order.quantity <= dec!(0) {
+                ExecutionStatus::Filled
+            } else {
+                ExecutionStatus::PartiallyFilled
+            };
+            if let Some(progress) = fills.get_mut(&order.id) {
+                progress.fill(trade_price, trade_quantity);
+            }
+            report_execution(
+                fills,
+                events,
+                order.id,
+                status,
+                Some((trade_price, trade_quantity)),
+            );
+        }
+
         if maker_order.quantity <= dec!(0) {
             orders_to_remove_indices.push(idx);
             // Now using the passed-in `order_map`
@@ -473,16 +2050,20 @@ fn process_level(
         level.orders.remove(idx);
     }
 
-    deltas.push(OrderBookDelta {
-        action: if level.orders.is_empty() {
-            DeltaAction::Delete
-        } else {
-            DeltaAction::Update
-        },
-        side: maker_side,
-        price: trade_price,
-        new_quantity: level.total_quantity,
-    });
+    // A taker canceled by self-trade prevention before touching anything leaves the level as is.
+    if level.total_quantity != initial_level_quantity {
+        deltas.push(OrderBookDelta {
+            action: if level.orders.is_empty() {
+                DeltaAction::Delete
+            } else {
+                DeltaAction::Update
+            },
+            side: maker_side,
+            price: trade_price,
+            new_quantity: level.total_quantity,
+            sequence: 0,
+        });
+    }
 
     (trades, deltas)
 }
@@ -0,0 +1,594 @@
+// redis_transport.rs
+use crate::journal::CommandSource;
+use crate::matching_engine::{
+    DeltaAction, EngineEvent, MarketStatus, OrderBookDelta, OrderBookSnapshot, OrderSide, Trade,
+};
+use crate::transport::{
+    CommandOutput, InboundCommand, InputSource, Output, OutputSink, StopSignal,
+};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use redis::aio::ConnectionManager;
+use redis::streams::{StreamReadOptions, StreamReadReply};
+use redis::{AsyncCommands, RedisResult, pipe};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+// Consumer group and consumer name used in `Transport::Streams` mode. The consumer name
+// must stay the same across restarts for unacknowledged entries to be picked up again.
+const STREAM_GROUP: &str = "matching-engine";
+const STREAM_CONSUMER: &str = "engine";
+// Every stream entry carries its JSON payload in this field.
+const STREAM_DATA_FIELD: &str = "data";
+const STREAM_READ_COUNT: usize = 100;
+// Longest a blocking read waits for a command before checking whether to stop.
+const READ_BLOCK: Duration = Duration::from_secs(1);
+
+/// How commands reach the engine and how processed orders and trades leave it.
+/// The key names are the same in both modes; only the Redis data type changes.
+/// Written as `lists` or `streams` in every config source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    /// BRPOP on the command lists, LPUSH onto the processed lists. A command popped
+    /// just before a crash is lost.
+    #[serde(alias = "Lists")]
+    Lists,
+    /// XREADGROUP/XACK on the command streams, XADD onto the processed streams.
+    /// Commands are acknowledged after they are processed, and replayed if they were not.
+    #[serde(alias = "Streams")]
+    Streams,
+}
+
+/// What goes out on the deltas channel. Written as `batched` or `per-delta` in every
+/// config source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeltaFormat {
+    /// One `BookUpdate` per command with all of its trades and deltas.
+    #[serde(alias = "Batched")]
+    Batched,
+    /// One message per `OrderBookDelta`, kept for subscribers written against the old
+    /// format. They can observe a half-applied book between the messages of a single
+    /// command, and cannot detect a missed message: the deltas' sequence numbers skip
+    /// those taken by trades, which go out on the trades channel.
+    #[serde(alias = "PerDelta", alias = "perdelta")]
+    PerDelta,
+}
+
+/// Everything one command changed in the book, published as a single message so that
+/// subscribers apply it atomically.
+#[derive(Serialize, Debug, Clone)]
+pub struct BookUpdate {
+    pub symbol: String,
+    /// Journal sequence number of the command that caused this update.
+    pub command_id: u64,
+    pub command: &'static str,
+    /// Sequence of the first trade or delta in this update. A subscriber that last saw
+    /// anything other than `first_sequence - 1` has missed an update.
+    pub first_sequence: u64,
+    /// Sequence of the last trade or delta in this update.
+    pub sequence: u64,
+    pub trades: Vec<Trade>,
+    pub deltas: Vec<OrderBookDelta>,
+}
+
+impl BookUpdate {
+    /// The update for one command's output, or `None` if it neither traded nor changed
+    /// the book.
+    pub fn from_output(output: &CommandOutput) -> Option<BookUpdate> {
+        // Trades are numbered before deltas, so they bound the update's range.
+        let first_sequence = output
+            .trades
+            .first()
+            .map(|t| t.sequence)
+            .or(output.deltas.first().map(|d| d.sequence))?;
+        let sequence = output
+            .deltas
+            .last()
+            .map(|d| d.sequence)
+            .or(output.trades.last().map(|t| t.sequence))?;
+        Some(BookUpdate {
+            symbol: output.symbol.clone(),
+            command_id: output.command_id,
+            command: output.command,
+            first_sequence,
+            sequence,
+            trades: output.trades.clone(),
+            deltas: output.deltas.clone(),
+        })
+    }
+}
+
+/// Redis key and channel names of one market.
+#[derive(Debug, Clone)]
+pub struct RedisKeys {
+    pub orders: String,
+    pub cancels: String,
+    pub snapshot_requests: String,
+    /// Admin commands such as `SetMarketStatus`.
+    pub admin: String,
+    pub trades_channel: String,
+    pub deltas_channel: String,
+    pub events_channel: String,
+    // These queues seed the db with the trades/orders processed by the matching engine.
+    pub processed_orders: String,
+    pub processed_trades: String,
+    pub rejections: String,
+    pub ltp: String,
+    /// The market's current `MarketStatus`, as its JSON string.
+    pub status: String,
+    // Each side of the Redis book mirror is a sorted set of exact price strings (the index)
+    // plus a hash from the same price string to the level's quantity.
+    pub bids: String,
+    pub asks: String,
+    pub bid_levels: String,
+    pub ask_levels: String,
+}
+
+impl RedisKeys {
+    /// The names for the market whose key part is `BASE_QUOTE`. A non-empty `prefix` is
+    /// prepended to every name, separated by `:`, so several deployments can share one
+    /// Redis.
+    pub fn new(prefix: &str, symbol_key_part: &str) -> Self {
+        let key = |name: &str| match prefix {
+            "" => format!("{}:{}", name, symbol_key_part),
+            prefix => format!("{}:{}:{}", prefix, name, symbol_key_part),
+        };
+        let bids = key("orderbook:bids");
+        let asks = key("orderbook:asks");
+        RedisKeys {
+            orders: key("orderbook:orders"),
+            cancels: key("orderbook:cancel"),
+            snapshot_requests: format!("{}:requests", key("orderbook:snapshot")),
+            admin: key("orderbook:admin"),
+            trades_channel: key("orderbook:trades"),
+            deltas_channel: key("orderbook:deltas"),
+            events_channel: key("orderbook:events"),
+            processed_orders: key("engine:processed_orders"),
+            processed_trades: key("engine:processed_trades"),
+            rejections: key("engine:rejections"),
+            ltp: key("orderbook:ltp"),
+            status: key("orderbook:status"),
+            bid_levels: format!("{}:levels", bids),
+            ask_levels: format!("{}:levels", asks),
+            bids,
+            asks,
+        }
+    }
+
+    fn inbound(&self) -> [&str; 4] {
+        [
+            &self.orders,
+            &self.cancels,
+            &self.snapshot_requests,
+            &self.admin,
+        ]
+    }
+
+    /// The sorted-set index and the quantity hash for one side of the Redis book.
+    fn book_keys(&self, side: OrderSide) -> (&str, &str) {
+        match side {
+            OrderSide::Buy => (&self.bids, &self.bid_levels),
+            OrderSide::Sell => (&self.asks, &self.ask_levels),
+        }
+    }
+
+    /// Queues one command's level changes to the Redis book mirror.
+    pub fn queue_orderbook_update(
+        &self,
+        pipeline: &mut redis::Pipeline,
+        deltas: &[OrderBookDelta],
+    ) {
+        for delta in deltas {
+            let (index_key, levels_key) = self.book_keys(delta.side);
+            let price = price_member(delta.price);
+            match delta.action {
+                DeltaAction::Delete => {
+                    pipeline.zrem(index_key, &price).ignore();
+                    pipeline.hdel(levels_key, &price).ignore();
+                }
+                DeltaAction::New | DeltaAction::Update => {
+                    pipeline
+                        .zadd(index_key, &price, price_score(delta.price))
+                        .ignore();
+                    pipeline
+                        .hset(levels_key, &price, delta.new_quantity.to_string())
+                        .ignore();
+                }
+            }
+        }
+    }
+}
+
+/// Sorted-set score for a price level. Only used for ordering: the member holds the exact
+/// price, so levels whose prices round to the same `f64` stay distinct.
+pub fn price_score(price: Decimal) -> f64 {
+    price.to_f64().unwrap_or(0.0)
+}
+
+/// Canonical form of a price, so `100` and `100.00` name the same level in Redis.
+pub fn price_member(price: Decimal) -> String {
+    price.normalize().to_string()
+}
+
+/// Reads commands from the market's Redis lists or streams.
+///
+/// Uses its own connection: the blocking reads would otherwise hold up every other command
+/// on a shared multiplexed connection.
+pub struct RedisInput {
+    con: ConnectionManager,
+    transport: Transport,
+    keys: RedisKeys,
+    // Entries of the last stream read that have not been handed out yet.
+    buffered: VecDeque<InboundCommand>,
+    // Streams mode starts by re-reading entries delivered to us but never acknowledged:
+    // each stream still being reclaimed, with the ID of the last entry read back from it.
+    // Pending entries stay pending until they are acknowledged, so each read carries on
+    // after the last one rather than starting over.
+    reclaiming: Vec<(String, String)>,
+}
+
+impl RedisInput {
+    pub async fn connect(
+        redis_url: &str,
+        transport: Transport,
+        keys: RedisKeys,
+    ) -> RedisResult<Self> {
+        let mut con = ConnectionManager::new(redis::Client::open(redis_url)?).await?;
+        if transport == Transport::Streams {
+            for stream in keys.inbound() {
+                // Start from "0" so commands added before the group existed are not skipped.
+                let created: RedisResult<()> =
+                    con.xgroup_create_mkstream(stream, STREAM_GROUP, "0").await;
+                if let Err(e) = created
+                    && e.code() != Some("BUSYGROUP")
+                {
+                    error!("Failed to create consumer group on '{}': {}", stream, e);
+                }
+            }
+        }
+        let reclaiming = keys
+            .inbound()
+            .iter()
+            .map(|stream| (stream.to_string(), "0".to_string()))
+            .collect();
+        Ok(RedisInput {
+            con,
+            transport,
+            keys,
+            buffered: VecDeque::new(),
+            reclaiming,
+        })
+    }
+
+    async fn read_list(&mut self) -> RedisResult<()> {
+        let result: Option<(String, String)> = self
+            .con
+            .brpop(&self.keys.inbound(), READ_BLOCK.as_secs_f64())
+            .await?;
+        if let Some((queue, data_json)) = result {
+            self.buffered.push_back(InboundCommand {
+                admin: queue == self.keys.admin,
+                origin: queue,
+                data_json,
+                source: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the inbound streams through the `STREAM_GROUP` consumer group. Entries are
+    /// acknowledged only once they have been journaled, applied and published, so whatever
+    /// was delivered but not acknowledged before a crash is read again from this
+    /// consumer's pending list on the next start.
+    async fn read_streams(&mut self) -> RedisResult<()> {
+        // An ID re-reads our unacknowledged entries after it; ">" asks for new ones, which
+        // are only read once every stream has been reclaimed.
+        let options = StreamReadOptions::default()
+            .group(STREAM_GROUP, STREAM_CONSUMER)
+            .count(STREAM_READ_COUNT);
+        let reply: StreamReadReply = if self.reclaiming.is_empty() {
+            let options = options.block(READ_BLOCK.as_millis() as usize);
+            self.con
+                .xread_options(&self.keys.inbound(), &[">"; 4], &options)
+                .await?
+        } else {
+            let (streams, ids): (Vec<&str>, Vec<&str>) = self
+                .reclaiming
+                .iter()
+                .map(|(stream, id)| (stream.as_str(), id.as_str()))
+                .unzip();
+            self.con.xread_options(&streams, &ids, &options).await?
+        };
+
+        if !self.reclaiming.is_empty() {
+            self.reclaiming.retain_mut(|(stream, last_id)| {
+                let key = reply.keys.iter().find(|key| key.key == *stream);
+                match key.and_then(|key| key.ids.last()) {
+                    Some(entry) => {
+                        *last_id = entry.id.clone();
+                        true
+                    }
+                    None => false,
+                }
+            });
+            let entries: usize = reply.keys.iter().map(|key| key.ids.len()).sum();
+            if entries > 0 {
+                info!("Re-claiming {} unacknowledged stream entries", entries);
+            }
+        }
+        for key in reply.keys {
+            for entry in key.ids {
+                let Some(data_json) = entry.get::<String>(STREAM_DATA_FIELD) else {
+                    warn!(
+                        "Stream entry {} on '{}' has no '{}' field",
+                        entry.id, key.key, STREAM_DATA_FIELD
+                    );
+                    let _: RedisResult<i32> =
+                        self.con.xack(&key.key, STREAM_GROUP, &[&entry.id]).await;
+                    continue;
+                };
+                self.buffered.push_back(InboundCommand {
+                    admin: key.key == self.keys.admin,
+                    origin: key.key.clone(),
+                    data_json,
+                    source: Some(CommandSource {
+                        stream: key.key.clone(),
+                        id: entry.id,
+                    }),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InputSource for RedisInput {
+    async fn next_command(&mut self, stop: &mut StopSignal) -> Option<InboundCommand> {
+        loop {
+            if let Some(command) = self.buffered.pop_front() {
+                return Some(command);
+            }
+            // Reads are never cancelled, since a popped command would be lost; they block
+            // for at most `READ_BLOCK` so a stop is noticed soon after it is requested.
+            if stop.is_stopped() {
+                return None;
+            }
+            let read = match self.transport {
+                Transport::Lists => self.read_list().await,
+                Transport::Streams => self.read_streams().await,
+            };
+            if let Err(e) = read {
+                error!("Error from Redis: {}. Retrying in 2s...", e);
+                tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+            }
+        }
+    }
+}
+
+/// Publishes outputs to the market's Redis channels and queues, and keeps the Redis book
+/// mirror up to date.
+pub struct RedisSink {
+    con: ConnectionManager,
+    transport: Transport,
+    delta_format: DeltaFormat,
+    keys: RedisKeys,
+}
+
+impl RedisSink {
+    pub async fn connect(
+        redis_url: &str,
+        transport: Transport,
+        delta_format: DeltaFormat,
+        keys: RedisKeys,
+    ) -> RedisResult<Self> {
+        let con = ConnectionManager::new(redis::Client::open(redis_url)?).await?;
+        Ok(RedisSink {
+            con,
+            transport,
+            delta_format,
+            keys,
+        })
+    }
+
+    /// Queues `json` onto one of the processed queues the DB worker consumes.
+    fn push_processed(&self, pipeline: &mut redis::Pipeline, key: &str, json: String) {
+        match self.transport {
+            Transport::Lists => pipeline.lpush(key, json).ignore(),
+            Transport::Streams => pipeline
+                .xadd(key, "*", &[(STREAM_DATA_FIELD, json)])
+                .ignore(),
+        };
+    }
+
+    /// Queues the deltas-channel message(s) for one command: a single `BookUpdate`, or one
+    /// message per delta with `DeltaFormat::PerDelta`.
+    fn queue_deltas(&self, pipeline: &mut redis::Pipeline, output: &CommandOutput) {
+        match self.delta_format {
+            DeltaFormat::Batched => {
+                let Some(update) = BookUpdate::from_output(output) else {
+                    return;
+                };
+                match serde_json::to_string(&update) {
+                    Ok(update_json) => {
+                        pipeline
+                            .publish(&self.keys.deltas_channel, update_json)
+                            .ignore();
+                    }
+                    Err(e) => {
+                        error!("[{}] Failed to serialize book update: {}", output.symbol, e)
+                    }
+                }
+            }
+            DeltaFormat::PerDelta => {
+                for delta in &output.deltas {
+                    if let Ok(delta_json) = serde_json::to_string(delta) {
+                        pipeline
+                            .publish(&self.keys.deltas_channel, delta_json)
+                            .ignore();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queues the live trade broadcasts, the DB worker's trade queue and the LTP.
+    fn queue_trades_and_ltp(&self, pipeline: &mut redis::Pipeline, trades: &[Trade]) {
+        for trade in trades {
+            if let Ok(trade_json) = serde_json::to_string(trade) {
+                pipeline
+                    .publish(&self.keys.trades_channel, &trade_json)
+                    .ignore();
+                self.push_processed(pipeline, &self.keys.processed_trades, trade_json);
+            }
+        }
+        if let Some(last_trade) = trades.last() {
+            pipeline
+                .set(&self.keys.ltp, last_trade.price.to_string())
+                .ignore();
+        }
+    }
+
+    fn queue_status(&self, pipeline: &mut redis::Pipeline, status: MarketStatus) {
+        // Serializes as the bare variant name.
+        if let Ok(status) = serde_json::to_value(status)
+            && let Some(status) = status.as_str()
+        {
+            pipeline.set(&self.keys.status, status).ignore();
+        }
+    }
+
+    fn queue_events(&self, pipeline: &mut redis::Pipeline, events: &[EngineEvent]) {
+        for event in events {
+            if let Ok(event_json) = serde_json::to_string(event) {
+                // Rejections also go to a durable queue so the API layer can settle the order.
+                if matches!(
+                    event,
+                    EngineEvent::OrderRejected { .. }
+                        | EngineEvent::CommandRejected { .. }
+                        | EngineEvent::DuplicateOrder { .. }
+                ) {
+                    self.push_processed(pipeline, &self.keys.rejections, event_json.clone());
+                }
+                pipeline
+                    .publish(&self.keys.events_channel, event_json)
+                    .ignore();
+            }
+            if let EngineEvent::MarketStatusChanged { status, .. } = event {
+                self.queue_status(pipeline, *status);
+            }
+            // Execution reports are the order's state history for the DB worker.
+            if let EngineEvent::ExecutionReport(report) = event {
+                match serde_json::to_string(report) {
+                    Ok(report_json) => {
+                        self.push_processed(pipeline, &self.keys.processed_orders, report_json)
+                    }
+                    Err(_) => error!("Failed to serialize execution report for DB queue"),
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl OutputSink for RedisSink {
+    /// Replaces the Redis book mirror with `snapshot` in one transaction.
+    async fn initialize(&mut self, snapshot: &OrderBookSnapshot) {
+        let mut transaction = pipe();
+        transaction.atomic();
+        transaction
+            .del(&[
+                &self.keys.bids,
+                &self.keys.asks,
+                &self.keys.bid_levels,
+                &self.keys.ask_levels,
+            ])
+            .ignore();
+        for (side, levels) in [
+            (OrderSide::Buy, &snapshot.bids),
+            (OrderSide::Sell, &snapshot.asks),
+        ] {
+            let (index_key, levels_key) = self.keys.book_keys(side);
+            for level in levels {
+                let price = price_member(level.price);
+                transaction
+                    .zadd(index_key, &price, price_score(level.price))
+                    .ignore();
+                transaction
+                    .hset(levels_key, &price, level.quantity.to_string())
+                    .ignore();
+            }
+        }
+        self.queue_status(&mut transaction, snapshot.status);
+        if let Err(e) = transaction.query_async::<_, ()>(&mut self.con).await {
+            error!(
+                "[{}] Failed to initialize Redis orderbook: {}",
+                snapshot.symbol, e
+            );
+            return;
+        }
+
+        info!(
+            "[{}] Redis orderbook initialized with {} bids and {} asks",
+            snapshot.symbol,
+            snapshot.bids.len(),
+            snapshot.asks.len()
+        );
+    }
+
+    /// Sends everything one command produced (book mirror, deltas, trades, LTP and events)
+    /// in one MULTI/EXEC round trip, so readers never see part of a command.
+    async fn publish(&mut self, output: &Output) -> io::Result<()> {
+        match output {
+            Output::Command(output) => {
+                let mut pipeline = pipe();
+                pipeline.atomic();
+                self.keys
+                    .queue_orderbook_update(&mut pipeline, &output.deltas);
+                self.queue_deltas(&mut pipeline, output);
+                self.queue_trades_and_ltp(&mut pipeline, &output.trades);
+                self.queue_events(&mut pipeline, &output.events);
+                pipeline
+                    .query_async::<_, ()>(&mut self.con)
+                    .await
+                    .map_err(io::Error::other)
+            }
+            Output::Events(events) => {
+                let mut pipeline = pipe();
+                self.queue_events(&mut pipeline, events);
+                pipeline
+                    .query_async::<_, ()>(&mut self.con)
+                    .await
+                    .map_err(io::Error::other)
+            }
+            Output::Snapshot {
+                response_channel,
+                snapshot,
+            } => {
+                let snapshot_json = serde_json::to_string(snapshot)?;
+                self.con
+                    .publish::<_, _, ()>(response_channel, snapshot_json)
+                    .await
+                    .map_err(io::Error::other)?;
+                debug!(
+                    "[{}] Snapshot sent to channel: {}",
+                    snapshot.symbol, response_channel
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn acknowledge(&mut self, source: &CommandSource) -> io::Result<()> {
+        self.con
+            .xack::<_, _, _, ()>(&source.stream, STREAM_GROUP, &[&source.id])
+            .await
+            .map_err(io::Error::other)
+    }
+}
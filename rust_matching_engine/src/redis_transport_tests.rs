@@ -0,0 +1,162 @@
+use crate::matching_engine::*;
+use crate::redis_transport::*;
+use crate::transport::CommandOutput;
+use chrono::Utc;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+fn limit_order(side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+    Order {
+        id: Uuid::new_v4(),
+        user_id: Uuid::new_v4(),
+        order_type: OrderType::Limit,
+        side,
+        price,
+        quantity,
+        timestamp: Utc::now(),
+        time_in_force: TimeInForce::Gtc,
+        stop_price: None,
+        post_only: None,
+        self_trade_prevention: None,
+        client_order_id: None,
+    }
+}
+
+fn delta(action: DeltaAction, price: Decimal, new_quantity: Decimal) -> OrderBookDelta {
+    OrderBookDelta {
+        action,
+        side: OrderSide::Buy,
+        price,
+        new_quantity,
+        sequence: 1,
+    }
+}
+
+/// The queued commands with their arguments, as strings.
+fn queued(pipeline: &redis::Pipeline) -> Vec<Vec<String>> {
+    pipeline
+        .cmd_iter()
+        .map(|cmd| {
+            cmd.args_iter()
+                .map(|arg| match arg {
+                    redis::Arg::Simple(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+                    redis::Arg::Cursor => "<cursor>".to_string(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn command_output(
+    command_id: u64,
+    trades: Vec<Trade>,
+    deltas: Vec<OrderBookDelta>,
+) -> CommandOutput {
+    CommandOutput {
+        symbol: "TEST".to_string(),
+        command: "NewOrder",
+        command_id,
+        trades,
+        deltas,
+        events: Vec::new(),
+    }
+}
+
+#[test]
+fn test_book_update_spans_trades_then_deltas() {
+    let mut engine = MatchingEngine::new("TEST".to_string());
+    engine.add_order(limit_order(OrderSide::Sell, dec!(100), dec!(5)));
+    // Takes 3 of the resting 5: a trade, then the delta that shrinks the level.
+    let (trades, deltas) = engine.add_order(limit_order(OrderSide::Buy, dec!(101), dec!(3)));
+    assert_eq!(trades.len(), 1);
+    assert_eq!(deltas.len(), 1);
+
+    let update = BookUpdate::from_output(&command_output(2, trades, deltas)).unwrap();
+    assert_eq!(update.command_id, 2);
+    assert_eq!(update.first_sequence, 2);
+    assert_eq!(update.sequence, 3);
+    assert_eq!(update.trades[0].sequence, 2);
+    assert_eq!(update.deltas[0].sequence, 3);
+}
+
+#[test]
+fn test_book_update_with_only_deltas_or_only_trades() {
+    let mut engine = MatchingEngine::new("TEST".to_string());
+    let (trades, deltas) = engine.add_order(limit_order(OrderSide::Sell, dec!(100), dec!(5)));
+    assert!(trades.is_empty());
+    let update = BookUpdate::from_output(&command_output(1, trades, deltas)).unwrap();
+    assert_eq!((update.first_sequence, update.sequence), (1, 1));
+
+    let (trades, _) = engine.add_order(limit_order(OrderSide::Buy, dec!(100), dec!(2)));
+    let update = BookUpdate::from_output(&command_output(2, trades, Vec::new())).unwrap();
+    assert_eq!((update.first_sequence, update.sequence), (2, 2));
+    assert!(update.deltas.is_empty());
+}
+
+#[test]
+fn test_book_updates_of_successive_commands_are_contiguous() {
+    let mut engine = MatchingEngine::new("TEST".to_string());
+    let mut last_sequence = 0;
+    let orders = [
+        limit_order(OrderSide::Sell, dec!(100), dec!(5)),
+        limit_order(OrderSide::Sell, dec!(101), dec!(5)),
+        limit_order(OrderSide::Buy, dec!(101), dec!(7)),
+        limit_order(OrderSide::Buy, dec!(99), dec!(1)),
+    ];
+    for (command_id, order) in (1..).zip(orders) {
+        let (trades, deltas) = engine.add_order(order);
+        let update = BookUpdate::from_output(&command_output(command_id, trades, deltas)).unwrap();
+        // Trades included, nothing is skipped between one update and the next.
+        assert_eq!(update.first_sequence, last_sequence + 1);
+        last_sequence = update.sequence;
+    }
+    assert_eq!(last_sequence, engine.sequence());
+}
+
+#[test]
+fn test_no_book_update_without_trades_or_deltas() {
+    assert!(BookUpdate::from_output(&command_output(1, Vec::new(), Vec::new())).is_none());
+}
+
+#[test]
+fn test_price_member_is_the_same_for_equal_prices() {
+    assert_eq!(price_member(dec!(100)), "100");
+    assert_eq!(price_member(dec!(100.00)), "100");
+    assert_eq!(price_member(dec!(100.50)), "100.5");
+    assert_eq!(price_member(dec!(0.000100)), "0.0001");
+    assert_eq!(price_score(dec!(100)), price_score(dec!(100.00)));
+    assert_eq!(price_score(dec!(100.5)), 100.5);
+}
+
+#[test]
+fn test_orderbook_update_adds_and_deletes_one_member_per_level() {
+    let keys = RedisKeys::new("", "TEST_INR");
+    let mut pipeline = redis::pipe();
+    keys.queue_orderbook_update(
+        &mut pipeline,
+        &[
+            delta(DeltaAction::New, dec!(100.00), dec!(5)),
+            delta(DeltaAction::Update, dec!(100), dec!(3)),
+            delta(DeltaAction::Delete, dec!(100.0), dec!(0)),
+        ],
+    );
+
+    let command = |cmd: &str, key: &str, rest: &[&str]| {
+        let mut args = vec![cmd.to_string(), key.to_string()];
+        args.extend(rest.iter().map(|arg| arg.to_string()));
+        args
+    };
+    assert_eq!(
+        queued(&pipeline),
+        vec![
+            command("ZADD", "orderbook:bids:TEST_INR", &["100.0", "100"]),
+            command("HSET", "orderbook:bids:TEST_INR:levels", &["100", "5"]),
+            command("ZADD", "orderbook:bids:TEST_INR", &["100.0", "100"]),
+            command("HSET", "orderbook:bids:TEST_INR:levels", &["100", "3"]),
+            // The delete removes both the index member and the quantity field.
+            command("ZREM", "orderbook:bids:TEST_INR", &["100"]),
+            command("HDEL", "orderbook:bids:TEST_INR:levels", &["100"]),
+        ]
+    );
+}
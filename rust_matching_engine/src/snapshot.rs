@@ -0,0 +1,159 @@
+// snapshot.rs
+use crate::matching_engine::EngineState;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"MESN";
+/// Bumped whenever `EngineState` or `SnapshotBody` changes shape. Older files are refused.
+pub const SNAPSHOT_VERSION: u32 = 5;
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotBody {
+    journal_sequence: u64,
+    taken_at: DateTime<Utc>,
+    stream_positions: BTreeMap<String, String>,
+    state: EngineState,
+}
+
+/// A loaded snapshot: the engine state after applying every journal entry up to
+/// and including `journal_sequence`.
+pub struct Snapshot {
+    pub journal_sequence: u64,
+    pub taken_at: DateTime<Utc>,
+    /// Last applied entry id per inbound Redis stream, when commands arrive over streams.
+    pub stream_positions: BTreeMap<String, String>,
+    pub state: EngineState,
+}
+
+/// Snapshot files of one market, named `{name}-{journal_sequence}.snapshot` in `dir`.
+///
+/// Each file is the magic bytes, the format version as a little-endian `u32`, then the
+/// bincode-encoded body.
+pub struct SnapshotStore {
+    dir: PathBuf,
+    name: String,
+    keep: usize,
+}
+
+impl SnapshotStore {
+    /// `keep` is how many of the newest snapshots survive pruning (at least one).
+    pub fn new(dir: &Path, name: &str, keep: usize) -> Self {
+        SnapshotStore {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            keep: keep.max(1),
+        }
+    }
+
+    /// Writes `state` atomically (temp file, fsync, rename) and prunes older snapshots.
+    pub fn write(
+        &self,
+        journal_sequence: u64,
+        stream_positions: &BTreeMap<String, String>,
+        state: &EngineState,
+    ) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let body = SnapshotBody {
+            journal_sequence,
+            taken_at: Utc::now(),
+            stream_positions: stream_positions.clone(),
+            state: state.clone(),
+        };
+        let encoded = bincode::serialize(&body).map_err(io::Error::other)?;
+
+        let path = self.path_for(journal_sequence);
+        let tmp_path = path.with_extension("snapshot.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(SNAPSHOT_MAGIC)?;
+        file.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        file.write_all(&encoded)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Loads the newest snapshot that can be read, skipping (and reporting) unreadable ones.
+    pub fn load_latest(&self) -> io::Result<Option<Snapshot>> {
+        for (_, path) in self.list()?.into_iter().rev() {
+            match read_snapshot(&path) {
+                Ok(snapshot) => return Ok(Some(snapshot)),
+                Err(e) => warn!("Skipping unreadable snapshot '{}': {}", path.display(), e),
+            }
+        }
+        Ok(None)
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let snapshots = self.list()?;
+        let excess = snapshots.len().saturating_sub(self.keep);
+        for (_, path) in snapshots.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn path_for(&self, journal_sequence: u64) -> PathBuf {
+        self.dir
+            .join(format!("{}-{:020}.snapshot", self.name, journal_sequence))
+    }
+
+    /// This market's snapshot files, oldest first.
+    fn list(&self) -> io::Result<Vec<(u64, PathBuf)>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let prefix = format!("{}-", self.name);
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let sequence = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|rest| rest.strip_suffix(".snapshot"))
+                .and_then(|sequence| sequence.parse::<u64>().ok());
+            if let Some(sequence) = sequence {
+                snapshots.push((sequence, path));
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots)
+    }
+}
+
+fn read_snapshot(path: &Path) -> io::Result<Snapshot> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    if bytes.len() < 8 || &bytes[..4] != SNAPSHOT_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a matching engine snapshot",
+        ));
+    }
+    let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    if version != SNAPSHOT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "snapshot version {} is not supported (expected {})",
+                version, SNAPSHOT_VERSION
+            ),
+        ));
+    }
+    let body: SnapshotBody = bincode::deserialize(&bytes[8..])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Snapshot {
+        journal_sequence: body.journal_sequence,
+        taken_at: body.taken_at,
+        stream_positions: body.stream_positions,
+        state: body.state,
+    })
+}
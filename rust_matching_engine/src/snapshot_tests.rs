@@ -0,0 +1,144 @@
+use crate::matching_engine::*;
+use crate::snapshot::*;
+use chrono::Utc;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+fn temp_snapshot_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("snapshot-test-{}", Uuid::new_v4()))
+}
+
+fn order(order_type: OrderType, side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+    Order {
+        id: Uuid::new_v4(),
+        user_id: Uuid::new_v4(),
+        order_type,
+        side,
+        price,
+        quantity,
+        timestamp: Utc::now(),
+        time_in_force: TimeInForce::Gtc,
+        stop_price: None,
+        post_only: None,
+        self_trade_prevention: None,
+        client_order_id: None,
+    }
+}
+
+/// Two asks queued at 101, one bid, a pending stop and a partial fill behind them.
+fn populated_engine() -> (MatchingEngine, Order, Order, Order) {
+    let mut engine = MatchingEngine::new("TEST".to_string());
+    let first_ask = order(OrderType::Limit, OrderSide::Sell, dec!(101), dec!(2));
+    let second_ask = order(OrderType::Limit, OrderSide::Sell, dec!(101), dec!(3));
+    let mut stop = order(OrderType::StopMarket, OrderSide::Buy, dec!(0), dec!(1));
+    stop.stop_price = Some(dec!(105));
+    engine.add_order(first_ask.clone());
+    engine.add_order(second_ask.clone());
+    engine.add_order(order(OrderType::Limit, OrderSide::Buy, dec!(99), dec!(4)));
+    engine.add_order(stop.clone());
+    engine.add_order(order(OrderType::Market, OrderSide::Buy, dec!(0), dec!(1)));
+    engine.take_events();
+    (engine, first_ask, second_ask, stop)
+}
+
+#[test]
+fn test_engine_state_round_trip_preserves_book() {
+    let (mut original, first_ask, second_ask, stop) = populated_engine();
+    original.set_status(MarketStatus::CancelOnly);
+    original.take_events();
+    let mut restored = MatchingEngine::new("TEST".to_string());
+    restored.restore_state(original.export_state());
+
+    let before = original.get_order_book_snapshot();
+    let after = restored.get_order_book_snapshot();
+    assert_eq!(after.bids[0].price, before.bids[0].price);
+    assert_eq!(after.bids[0].quantity, before.bids[0].quantity);
+    assert_eq!(after.asks[0].quantity, dec!(4));
+    assert_eq!(after.last_traded_price, Some(dec!(101)));
+    assert_eq!(after.sequence, before.sequence);
+    assert_eq!(after.status, MarketStatus::CancelOnly);
+    assert_eq!(
+        restored.get_order_by_id(stop.id).unwrap().stop_price,
+        Some(dec!(105))
+    );
+
+    original.set_status(MarketStatus::Trading);
+    restored.set_status(MarketStatus::Trading);
+
+    // FIFO order and fill progress survive: the partially filled first ask trades first
+    // and reports its cumulative fill.
+    let taker = order(OrderType::Market, OrderSide::Buy, dec!(0), dec!(2));
+    let (original_trades, _) = original.add_order(taker.clone());
+    let (restored_trades, _) = restored.add_order(taker);
+    assert_eq!(restored_trades.len(), original_trades.len());
+    assert_eq!(restored_trades[0].maker_order_id, first_ask.id);
+    assert_eq!(restored_trades[0].quantity, dec!(1));
+    assert_eq!(restored_trades[1].maker_order_id, second_ask.id);
+    assert_eq!(restored_trades[0].sequence, original_trades[0].sequence);
+
+    let first_ask_report = restored
+        .take_events()
+        .into_iter()
+        .find_map(|event| match event {
+            EngineEvent::ExecutionReport(report) if report.order_id == first_ask.id => Some(report),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(first_ask_report.status, ExecutionStatus::Filled);
+    assert_eq!(first_ask_report.filled_quantity, dec!(2));
+
+    // Cancelling the restored stop works through the rebuilt index.
+    let (result, _) = restored.cancel_order(stop.id);
+    assert!(result.is_ok());
+}
+
+#[test]
+fn test_snapshot_store_writes_and_loads_latest() {
+    let dir = temp_snapshot_dir();
+    let store = SnapshotStore::new(&dir, "TEST", 2);
+    assert!(store.load_latest().unwrap().is_none());
+
+    let (engine, ..) = populated_engine();
+    let state = engine.export_state();
+    store.write(10, &BTreeMap::new(), &state).unwrap();
+    store.write(20, &BTreeMap::new(), &state).unwrap();
+    store.write(30, &BTreeMap::new(), &state).unwrap();
+
+    let snapshot = store.load_latest().unwrap().unwrap();
+    assert_eq!(snapshot.journal_sequence, 30);
+    assert_eq!(snapshot.state.symbol(), "TEST");
+    let mut restored = MatchingEngine::new("TEST".to_string());
+    restored.restore_state(snapshot.state);
+    assert_eq!(restored.get_order_book_snapshot().asks[0].quantity, dec!(4));
+
+    // Only the newest two are kept.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_snapshot_with_unknown_version_is_skipped() {
+    let dir = temp_snapshot_dir();
+    let store = SnapshotStore::new(&dir, "TEST", 5);
+    let (engine, ..) = populated_engine();
+    store
+        .write(10, &BTreeMap::new(), &engine.export_state())
+        .unwrap();
+    let newer = store
+        .write(20, &BTreeMap::new(), &engine.export_state())
+        .unwrap();
+
+    let mut bytes = fs::read(&newer).unwrap();
+    bytes[4..8].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+    fs::write(&newer, bytes).unwrap();
+
+    let snapshot = store.load_latest().unwrap().unwrap();
+    assert_eq!(snapshot.journal_sequence, 10);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,235 @@
+// transport.rs
+use crate::journal::CommandSource;
+use crate::matching_engine::{EngineEvent, OrderBookDelta, OrderBookSnapshot, Trade};
+use async_trait::async_trait;
+use log::error;
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+use tokio::sync::{mpsc, watch};
+
+/// One raw command as read from an `InputSource`.
+#[derive(Debug, Clone)]
+pub struct InboundCommand {
+    /// Where the command came from (queue, stream or file name), for logging.
+    pub origin: String,
+    /// The JSON-encoded `EngineCommand`.
+    pub data_json: String,
+    /// Set when the command came from a Redis stream entry that must be acknowledged.
+    pub source: Option<CommandSource>,
+    /// Set when the command came from the market's admin queue or stream, the only place
+    /// admin commands such as `SetMarketStatus` are accepted from. In-process channels and
+    /// offline files are in the operator's hands and always set it.
+    pub admin: bool,
+}
+
+/// Everything one command applied to the engine produced.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandOutput {
+    pub symbol: String,
+    pub command: &'static str,
+    /// Journal sequence number of the command.
+    pub command_id: u64,
+    pub trades: Vec<Trade>,
+    pub deltas: Vec<OrderBookDelta>,
+    pub events: Vec<EngineEvent>,
+}
+
+/// What the engine produced for one inbound command, handed to an `OutputSink` in
+/// command order.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "output", content = "data")]
+pub enum Output {
+    Command(CommandOutput),
+    /// Events for a command that never reached the engine (malformed or not journaled).
+    Events(Vec<EngineEvent>),
+    Snapshot {
+        response_channel: String,
+        snapshot: OrderBookSnapshot,
+    },
+}
+
+/// Set once the consumer reading from an `InputSource` has been asked to stop.
+#[derive(Clone)]
+pub struct StopSignal(watch::Receiver<bool>);
+
+impl StopSignal {
+    pub fn new(receiver: watch::Receiver<bool>) -> Self {
+        StopSignal(receiver)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once stopping has been requested.
+    pub async fn stopped(&mut self) {
+        // An error means the sender is gone, and with it whoever could have stopped us.
+        let _ = self.0.wait_for(|stopped| *stopped).await;
+    }
+}
+
+/// Where the engine's commands come from.
+#[async_trait]
+pub trait InputSource: Send {
+    /// Waits for the next command. `None` means the source is exhausted, or `stop` was
+    /// set, and the consumer should finish once everything read so far has been
+    /// published. Once `stop` is set this must return promptly, without dropping a
+    /// command it has already taken off its source.
+    async fn next_command(&mut self, stop: &mut StopSignal) -> Option<InboundCommand>;
+}
+
+/// Where the engine's outputs go.
+#[async_trait]
+pub trait OutputSink: Send {
+    /// Called once before the first output, with the book as it was restored at startup.
+    async fn initialize(&mut self, _snapshot: &OrderBookSnapshot) {}
+
+    /// Delivers one command's output. On an error the output may not have been delivered,
+    /// and the stream entry it came from is not acknowledged.
+    async fn publish(&mut self, output: &Output) -> io::Result<()>;
+
+    /// Called after the output of the command read from `source` has been published.
+    async fn acknowledge(&mut self, _source: &CommandSource) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Commands sent through an in-process channel, for tests and for embedding the engine.
+pub struct ChannelInput {
+    receiver: mpsc::Receiver<String>,
+}
+
+/// Returns the sender to push command JSON into, and the input that reads it. The input
+/// is exhausted once every sender has been dropped.
+pub fn channel_input(capacity: usize) -> (mpsc::Sender<String>, ChannelInput) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    (sender, ChannelInput { receiver })
+}
+
+#[async_trait]
+impl InputSource for ChannelInput {
+    async fn next_command(&mut self, stop: &mut StopSignal) -> Option<InboundCommand> {
+        // `recv` is cancel safe, so nothing is lost when the stop wins the race.
+        let data_json = tokio::select! {
+            biased;
+            _ = stop.stopped() => return None,
+            data_json = self.receiver.recv() => data_json?,
+        };
+        Some(InboundCommand {
+            origin: "channel".to_string(),
+            data_json,
+            source: None,
+            admin: true,
+        })
+    }
+}
+
+/// Forwards every output into an in-process channel.
+pub struct ChannelSink {
+    sender: mpsc::UnboundedSender<Output>,
+}
+
+/// Returns the sink and the receiver its outputs arrive on.
+pub fn channel_sink() -> (ChannelSink, mpsc::UnboundedReceiver<Output>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    (ChannelSink { sender }, receiver)
+}
+
+#[async_trait]
+impl OutputSink for ChannelSink {
+    async fn publish(&mut self, output: &Output) -> io::Result<()> {
+        // Nobody listening any more is not the engine's problem.
+        let _ = self.sender.send(output.clone());
+        Ok(())
+    }
+}
+
+/// Newline-delimited command JSON from a file or stdin, for offline runs. Blank lines are
+/// skipped.
+pub struct LineInput<R> {
+    lines: Lines<R>,
+    origin: String,
+}
+
+impl LineInput<BufReader<tokio::io::Stdin>> {
+    pub fn stdin() -> Self {
+        LineInput {
+            lines: BufReader::new(tokio::io::stdin()).lines(),
+            origin: "stdin".to_string(),
+        }
+    }
+}
+
+impl LineInput<BufReader<File>> {
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path).await?;
+        Ok(LineInput {
+            lines: BufReader::new(file).lines(),
+            origin: path.display().to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl<R: AsyncBufRead + Unpin + Send> InputSource for LineInput<R> {
+    async fn next_command(&mut self, stop: &mut StopSignal) -> Option<InboundCommand> {
+        loop {
+            // `next_line` is cancel safe: a partly read line stays buffered.
+            let line = tokio::select! {
+                biased;
+                _ = stop.stopped() => return None,
+                line = self.lines.next_line() => line,
+            };
+            match line {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => {
+                    return Some(InboundCommand {
+                        origin: self.origin.clone(),
+                        data_json: line,
+                        source: None,
+                        admin: true,
+                    });
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    error!("Failed to read commands from '{}': {}", self.origin, e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Writes every output as one line of JSON to a file or stdout.
+pub struct JsonLinesSink<W> {
+    writer: W,
+}
+
+impl JsonLinesSink<tokio::io::Stdout> {
+    pub fn stdout() -> Self {
+        JsonLinesSink {
+            writer: tokio::io::stdout(),
+        }
+    }
+}
+
+impl JsonLinesSink<File> {
+    pub async fn create(path: &Path) -> io::Result<Self> {
+        Ok(JsonLinesSink {
+            writer: File::create(path).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl<W: AsyncWrite + Unpin + Send> OutputSink for JsonLinesSink<W> {
+    async fn publish(&mut self, output: &Output) -> io::Result<()> {
+        let mut line = serde_json::to_vec(output)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        self.writer.flush().await
+    }
+}
@@ -33,6 +33,16 @@ interface DeltaData {
   };
 }
 
+// One command's trades and deltas, applied together so the book never shows half of it.
+interface BookUpdateData {
+  channel: string;
+  data: {
+    first_sequence: number;
+    sequence: number;
+    deltas: DeltaData["data"][];
+  };
+}
+
 interface TradeData {
   channel: string;
   data: {
@@ -97,6 +107,13 @@ export function OrderBookProvider({ children }: { children: ReactNode }) {
           break;
         }
 
+        case "orderbook_update": {
+          console.log("Received orderbook update:", message.data);
+          const { channel, data } = message.data as BookUpdateData;
+          data.deltas.forEach((delta) => handleOrderBookDelta({ channel, data: delta }));
+          break;
+        }
+
         case "trade": {
           console.log("Received trade:", message.data);
           const tradeData = message.data as TradeData;
@@ -533,7 +533,9 @@ class TradingWebSocketServer {
 
           let eventType: string;
           if (channel.includes("deltas")) {
-            eventType = "orderbook_delta";
+            // The engine's default batched format sends one update per command, with a
+            // `deltas` list; `--delta-format per-delta` sends one message per delta.
+            eventType = Array.isArray(data.deltas) ? "orderbook_update" : "orderbook_delta";
           } else if (channel.includes("trades")) {
             eventType = "trade";
           } else {
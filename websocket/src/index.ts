@@ -1,4 +1,4 @@
-import type { ClientWebSocket } from "@/types";
+import type { BookUpdate, ClientWebSocket } from "@/types";
 import { handleHttpRequest } from "@/api/httpHandlers";
 import { tradingPairs } from "@/config";
 import { redisService } from "@/services/redisService";
@@ -12,12 +12,50 @@ const redisChannels = tradingPairs.flatMap((pair) => {
   return [`orderbook:deltas:${symbolKey}`, `orderbook:trades:${symbolKey}`];
 });
 
+// Last book update sequence seen per deltas channel, to notice a missed update.
+const lastSequences = new Map<string, number>();
+
+// Sends the channel's subscribers the book from the Redis mirror, which already includes
+// whatever update was missed.
+async function resyncOrderbook(channel: string, symbol: string) {
+  try {
+    const orderbookData = await redisService.getOrderbook(symbol, 20);
+    connectionManager.broadcastToChannel(channel, {
+      event: "redis_orderbook",
+      payload: { symbol, ...orderbookData },
+    });
+  } catch (error) {
+    console.error(`Failed to resync ${symbol} after a missed book update:`, error);
+  }
+}
+
+function handleBookUpdate(channel: string, update: BookUpdate) {
+  const last = lastSequences.get(channel);
+  if (last !== undefined && update.first_sequence <= last) {
+    return; // Already forwarded.
+  }
+  lastSequences.set(channel, update.sequence);
+  connectionManager.broadcastToChannel(channel, {
+    event: "orderbook_update",
+    payload: { channel, ...update },
+  });
+  if (last !== undefined && update.first_sequence !== last + 1) {
+    console.warn(`Missed book updates ${last + 1}..${update.first_sequence - 1} on ${channel}, resyncing`);
+    resyncOrderbook(channel, update.symbol);
+  }
+}
+
 redisService.subscribe(redisChannels, (channel, message) => {
   try {
     const data = JSON.parse(message);
     let eventType: "orderbook_delta" | "trade";
 
     if (channel.includes(":deltas:")) {
+      // Batched format: one message per command. The per-delta format has no `deltas` list.
+      if (Array.isArray(data.deltas)) {
+        handleBookUpdate(channel, data as BookUpdate);
+        return;
+      }
       eventType = "orderbook_delta";
     } else if (channel.includes(":trades:")) {
       eventType = "trade";
@@ -50,26 +50,33 @@ class RedisService {
     const asksKey = `orderbook:asks:${symbolKey}`;
     const ltpKey = `orderbook:ltp:${symbolKey}`;
 
-    const parseLevels = (data: string[]): OrderbookLevel[] => {
+    // The sorted sets index the exact price strings; `:levels` hashes hold each level's quantity.
+    const readLevels = async (prices: string[], levelsKey: string): Promise<OrderbookLevel[]> => {
+      if (prices.length === 0) return [];
+      const quantities = await this.publisher.hmget(levelsKey, ...prices);
       const levels: OrderbookLevel[] = [];
-      for (let i = 0; i < data.length; i += 2) {
-        const [price, quantity] = (data[i] || "").split(":");
-        if (price && quantity) {
+      prices.forEach((price, i) => {
+        const quantity = quantities[i];
+        if (quantity) {
           levels.push({ price: parseFloat(price), quantity: parseFloat(quantity) });
         }
-      }
+      });
       return levels;
     };
 
-    const [bidsData, asksData, ltp] = await Promise.all([
-      this.publisher.zrevrange(bidsKey, 0, limit - 1, "WITHSCORES"),
-      this.publisher.zrange(asksKey, 0, limit - 1, "WITHSCORES"),
+    const [bidPrices, askPrices, ltp] = await Promise.all([
+      this.publisher.zrevrange(bidsKey, 0, limit - 1),
+      this.publisher.zrange(asksKey, 0, limit - 1),
       this.publisher.get(ltpKey),
     ]);
+    const [bids, asks] = await Promise.all([
+      readLevels(bidPrices, `${bidsKey}:levels`),
+      readLevels(askPrices, `${asksKey}:levels`),
+    ]);
 
     return {
-      bids: parseLevels(bidsData),
-      asks: parseLevels(asksData),
+      bids,
+      asks,
       last_traded_price: ltp ? parseFloat(ltp) : null,
     };
   }
@@ -65,6 +65,17 @@ export interface ErrorPayload {
   message: string;
 }
 
+// One command's trades and book deltas, published as a single message on the deltas channel.
+export interface BookUpdate {
+  symbol: string;
+  command_id: number;
+  command: string;
+  first_sequence: number;
+  sequence: number;
+  trades: any[];
+  deltas: any[];
+}
+
 export interface OrderbookSnapshotPayload {
   symbol: string;
   bids: OrderbookLevel[];
@@ -81,7 +92,8 @@ export type ServerMessage =
   | { event: "unsubscribed"; payload: { symbol: string } }
   | { event: "orderbook_snapshot"; payload: any } // from matching engine
   | { event: "redis_orderbook"; payload: OrderbookSnapshotPayload }
-  | { event: "orderbook_delta"; payload: any } // from matching engine
+  | { event: "orderbook_delta"; payload: any } // from matching engine, --delta-format per-delta
+  | { event: "orderbook_update"; payload: BookUpdate & { channel: string } } // from matching engine
   | { event: "trade"; payload: any } // from matching engine
   | { event: "error"; payload: ErrorPayload };
 